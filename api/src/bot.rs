@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use crate::{Card, Suit};
+use crate::rules::{points, rank, round_winner, Action, GameState};
+use crate::strategy::{self, PlayerView, Strategy};
+
+
+// Points on the table that are worth spending a trump on
+static TRUMP_WORTHY_POINTS: u8 = 10;
+
+/// Built-in strategies played by the server.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum BotLevel {
+    // Wins cheap tricks and keeps its trumps
+    #[default]
+    Simple,
+    // Also gives points to its partner, exchanges the trump card, declares,
+    // and fights for every point once the deck has run out
+    Strong
+}
+
+/// Next move of the bot sitting at seat. It must be that seat's turn.
+/// Bots only look at what the player at that seat can see: their hand, the trump card and the table.
+pub fn choose(state: &GameState, seat: u8, level: &BotLevel) -> Action {
+    strategy::next_action(&mut level.clone(), &PlayerView::new(state, seat))
+}
+
+impl Strategy for BotLevel {
+    fn exchange_trump(&mut self, _view: &PlayerView, _card: &Card) -> bool {
+        // The trump card is always better than the card given for it
+        *self == BotLevel::Strong
+    }
+
+    fn declare(&mut self, _view: &PlayerView, suits: &[Suit]) -> Option<Suit> {
+        if *self != BotLevel::Strong {
+            return None;
+        }
+        suits.first().cloned()
+    }
+
+    fn choose(&mut self, view: &PlayerView) -> Card {
+        let trump = &view.trump.suit;
+        // Cheapest cards first: plain cards before trumps, then by points and strength
+        let cost = |card: &&Card| (card.suit == *trump, points(card), rank(card));
+        let cheapest = view.hand.iter().min_by_key(cost).unwrap();
+
+        if view.trick.is_empty() {
+            // Lead with the cheapest card
+            return cheapest.clone();
+        }
+
+        let played = view.trick_cards();
+        let at_stake: u8 = played.iter().map(points).sum();
+        let cheapest_winner = view.hand
+            .iter()
+            .filter(|card| wins_trick(&played, card, &view.trump))
+            .min_by_key(cost);
+
+        if *self == BotLevel::Strong {
+            if view.partner().is_some() && view.partner() == view.trick_winner() {
+                // Our partner is taking the trick. Give them our most valuable plain card
+                return view.hand
+                    .iter()
+                    .filter(|card| card.suit != *trump)
+                    .max_by_key(|card| points(card))
+                    .unwrap_or(cheapest)
+                    .clone();
+            }
+            if view.deck == 0 && at_stake > 0 {
+                // No more cards to draw. Any point is worth a trump
+                if let Some(card) = cheapest_winner {
+                    return card.clone();
+                }
+            }
+        }
+
+        // Take the trick with a plain card whenever possible, and with a trump when it is worth it
+        match cheapest_winner {
+            Some(card) if card.suit != *trump || at_stake >= TRUMP_WORTHY_POINTS => card.clone(),
+            _ => cheapest.clone()
+        }
+    }
+}
+
+// The card would win the trick played so far
+fn wins_trick(played: &[Card], card: &Card, trump: &Card) -> bool {
+    let mut cards = played.to_vec();
+    cards.push(card.clone());
+    round_winner(&cards, &trump.suit) == played.len()
+}
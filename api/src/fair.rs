@@ -0,0 +1,225 @@
+//! Provably fair shuffles. Each deck is shuffled from a server seed mixed with a fresh seed from every player.
+//! The server publishes a hash of its seed before the players send theirs, a hash of its seed and the deck
+//! when the game starts, and reveals the seeds when it ends. Players can then check that the deck they played
+//! was the one committed to, and that the server could not pick its seed knowing theirs.
+
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use crate::{Card, Event, Reveal};
+use crate::rules;
+
+
+/// Longest seed a player can send for a deck.
+pub static MAX_CLIENT_SEED_LENGTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuditError {
+    // The game was joined midway, so not all of its events are known
+    Incomplete,
+    // Our seed was not mixed into the shuffle
+    ClientSeed,
+    // The revealed server seed is not the one committed to before the players sent their seeds
+    ServerSeed,
+    // The revealed seeds do not shuffle the deck committed to when the game started
+    Commitment,
+    // A card was dealt out of the order of the revealed deck
+    Dealt,
+    // A player played a card that was never dealt to them
+    Played
+}
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            AuditError::Incomplete => "Joined midway, so the deck cannot be checked",
+            AuditError::ClientSeed => "Our seed was not used to shuffle the deck",
+            AuditError::ServerSeed => "The server seed is not the one committed to before we sent ours",
+            AuditError::Commitment => "The revealed deck does not match the one committed to",
+            AuditError::Dealt => "A card was dealt out of the order of the revealed deck",
+            AuditError::Played => "A card was played that was never dealt"
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+/// Deck of a game shuffled from the server seed and the seeds of the players, in seat order.
+pub fn shuffle(num_players: u8, server_seed: &str, client_seeds: &[String]) -> Vec<Card> {
+    let mut hasher = Sha256::new();
+    for seed in std::iter::once(server_seed).chain(client_seeds.iter().map(String::as_str)) {
+        // Length prefixed, so that no two lists of seeds mix into the same bytes
+        hasher.update((seed.len() as u64).to_be_bytes());
+        hasher.update(seed.as_bytes());
+    }
+    let mut rng = ChaCha20Rng::from_seed(hasher.finalize().into());
+    let mut deck = rules::deck(num_players);
+    deck.shuffle(&mut rng);
+    deck
+}
+
+/// Hash of the server seed, published before the players send their seeds.
+pub fn seed_hash(server_seed: &str) -> String {
+    hex::encode(Sha256::digest(server_seed.as_bytes()))
+}
+
+/// Hash of the server seed and the shuffled deck, published when the game starts.
+pub fn commitment(server_seed: &str, deck: &[Card]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(server_seed.as_bytes());
+    for card in deck {
+        hasher.update([card.number, card.suit.clone() as u8]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Check a game against the seeds revealed when it ended: the server seed matches the hash sent with
+/// `SeedRequested`, the deck matches the commitment sent with `GameStart`, our seed was mixed in, and
+/// every card dealt and played follows from that deck. `events` are the events received by the player
+/// sitting at seat, from the request of the seeds to `GameEnd`, and `client_seed` the seed we sent.
+pub fn verify(seat: u8, client_seed: &str, events: &[Event], reveal: &Reveal) -> Result<(), AuditError> {
+    let Some(start) = events.iter().position(|event| matches!(event, Event::GameStart(..))) else {
+        return Err(AuditError::Incomplete);
+    };
+    let Event::GameStart(trump, teams, first, committed) = &events[start] else {
+        unreachable!()
+    };
+    let Some(requested) = events[..start].iter().rev().find_map(|event| match event {
+        Event::SeedRequested(hash) => Some(hash),
+        _ => None
+    }) else {
+        return Err(AuditError::Incomplete);
+    };
+    if seed_hash(&reveal.server_seed) != *requested {
+        return Err(AuditError::ServerSeed);
+    }
+    if reveal.client_seeds.get(seat as usize).map(String::as_str) != Some(client_seed) {
+        return Err(AuditError::ClientSeed);
+    }
+    let num_players = teams.len() as u8;
+    let mut deck = shuffle(num_players, &reveal.server_seed, &reveal.client_seeds);
+    if commitment(&reveal.server_seed, &deck) != *committed {
+        return Err(AuditError::Commitment);
+    }
+
+    // Deal the revealed deck the way the rules do, from the last card, starting with the first player
+    let received: Vec<&Card> = events[..start]
+        .iter()
+        .filter_map(|event| match event { Event::NewCard(card) => Some(card), _ => None })
+        .collect();
+    let mut hands = vec![Vec::new(); num_players as usize];
+    for i in 0..num_players {
+        let hand = &mut hands[((first + i) % num_players) as usize];
+        for _ in 0..received.len() {
+            hand.push(deck.pop().ok_or(AuditError::Dealt)?);
+        }
+    }
+    if deck.first() != Some(trump) || !received.into_iter().eq(hands[seat as usize].iter()) {
+        return Err(AuditError::Dealt);
+    }
+
+    // Cards we should draw at the end of each round, in order
+    let mut drawn = VecDeque::new();
+    for event in &events[start + 1..] {
+        match event {
+            Event::NewCard(card) if drawn.pop_front().as_ref() != Some(card) => {
+                return Err(AuditError::Dealt);
+            },
+            Event::PlayedCard(player, card) | Event::TrumpExchanged(player, card) => {
+                let hand = &mut hands[*player as usize];
+                let Some(position) = hand.iter().position(|c| c == card) else {
+                    return Err(AuditError::Played);
+                };
+                hand.remove(position);
+                if let Event::TrumpExchanged(..) = event {
+                    // The card takes the place of the trump card at the bottom of the deck
+                    let trump = deck.first_mut().ok_or(AuditError::Played)?;
+                    hand.push(std::mem::replace(trump, card.clone()));
+                }
+            },
+            Event::RoundEnd(trick, _) => {
+                // Every player draws a card, starting with the winner, while the deck lasts
+                for i in 0..deck.len().saturating_sub(trick.deck as usize) {
+                    let player = (trick.winner + i as u8) % num_players;
+                    let card = deck.pop().ok_or(AuditError::Dealt)?;
+                    if player == seat {
+                        drawn.push_back(card.clone());
+                    }
+                    hands[player as usize].push(card);
+                }
+            },
+            Event::GameEnd(..) => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::{self, BotLevel};
+    use crate::rules::{MatchState, Rules};
+    use crate::GameResult;
+
+    // Events received by the player at seat 0 during the first game of a match between two bots, and
+    // the seeds revealed at its end. Trumps can be exchanged, so cards also move between hand and deck
+    fn first_game() -> (Vec<Event>, Reveal) {
+        let rules = Rules { trump_exchange: true, ..Rules::default() };
+        let mut state = MatchState::new(2, rules, 1, 7).unwrap();
+        let mut events = vec![Event::SeedRequested(state.seed_commitment())];
+        let mut dealt = state.deal(&["ours".to_string(), "theirs".to_string()]).unwrap();
+        loop {
+            for event in dealt {
+                match event {
+                    rules::Event::Dealt(0, card) => events.push(Event::NewCard(card)),
+                    rules::Event::Started(trump, first) => {
+                        events.push(Event::GameStart(trump, state.game.teams.clone(), first, state.commitment.clone()));
+                    },
+                    rules::Event::Played(seat, card) => events.push(Event::PlayedCard(seat, card)),
+                    rules::Event::TrumpExchanged(seat, card) => events.push(Event::TrumpExchanged(seat, card)),
+                    rules::Event::RoundEnd(trick, totals) => events.push(Event::RoundEnd(trick, totals)),
+                    rules::Event::GameEnd(_, totals) => {
+                        // The result is not checked
+                        events.push(Event::GameEnd(GameResult::Draw, totals, state.reveal()));
+                        return (events, state.reveal());
+                    },
+                    _ => {}
+                }
+            }
+            let action = bot::choose(&state.game, state.game.turn, &BotLevel::Strong);
+            dealt = state.apply(action).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_fairly_dealt_game_is_verified() {
+        let (events, reveal) = first_game();
+        assert_eq!(verify(0, "ours", &events, &reveal), Ok(()));
+    }
+
+    #[test]
+    fn a_deck_shuffled_without_our_seed_is_rejected() {
+        let (events, reveal) = first_game();
+        assert_eq!(verify(0, "another", &events, &reveal), Err(AuditError::ClientSeed));
+    }
+
+    #[test]
+    fn a_server_seed_other_than_the_one_committed_to_is_rejected() {
+        let (events, mut reveal) = first_game();
+        reveal.server_seed = "chosen after seeing ours".to_string();
+        assert_eq!(verify(0, "ours", &events, &reveal), Err(AuditError::ServerSeed));
+    }
+
+    #[test]
+    fn cards_dealt_out_of_order_are_rejected() {
+        let (mut events, reveal) = first_game();
+        // Swap the first two cards of our hand
+        let first = events.iter().position(|event| matches!(event, Event::NewCard(_))).unwrap();
+        events.swap(first, first + 1);
+        assert_eq!(verify(0, "ours", &events, &reveal), Err(AuditError::Dealt));
+    }
+}
@@ -1,3 +1,8 @@
+pub mod bot;
+pub mod fair;
+pub mod rules;
+pub mod strategy;
+
 use serde::{Deserialize, Serialize};
 
 
@@ -20,14 +25,352 @@ pub struct Card {
 pub enum Event {
     Connected(Vec<String>),
     NewCard(Card),
-    GameStart(Card),
-    PlayedCard(Card),
-    RoundEnd(u8, u8),
-    GameEnd(String)
+    // Hash of the server seed of the next deck, committed to before the players send theirs.
+    // Each player answers with a seed of their own to mix into its shuffle
+    SeedRequested(String),
+    // Trump card, the team of the player sitting at each seat, the seat playing first
+    // and the commitment to the shuffled deck
+    GameStart(Card, Vec<u8>, u8, String),
+    // Seat of the player and the card played
+    PlayedCard(u8, Card),
+    // Seat that exchanged their card for the trump card and the new trump card.
+    // The previous trump card is now in that player's hand
+    TrumpExchanged(u8, Card),
+    // Seat that declared the king and horse of the suit, bonus points and the updated total of each team
+    Declared(u8, Suit, u8, Vec<u8>),
+    // Cards played in the round with who played them, who won it, and the updated total of each team
+    RoundEnd(rules::Trick, Vec<u8>),
+    // Result of the game, the final total of each team and the seeds the deck was shuffled with
+    GameEnd(GameResult, Vec<u8>, Reveal),
+    // Games won by each team so far in the match
+    MatchScore(Vec<u8>),
+    // Result of the match and the games won by each team. Last event of the stream
+    MatchEnd(GameResult, Vec<u8>),
+    // Player that left the game and how. The game carries on according to its LeavePolicy
+    PlayerLeft(String, Departure),
+    // Seat of the player to play and the deadline of their turn, in milliseconds since the Unix epoch.
+    // Only sent when the game has a turn time limit
+    TurnStarted(u8, u64),
+    // Full state of the game, sent when a player reconnects
+    Snapshot(GameSnapshot)
 }
 
+/// Event of a game as sent to one player. The sequence number is also the SSE event id,
+/// so a reconnecting client can resume from the last event it received.
 #[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventEnvelope {
+    pub game_id: String,
+    // Position of the event in the player's stream, starting at 1
+    pub seq: u64,
+    pub event: Event
+}
+
+/// State of a game as seen by one player. Other players' cards stay hidden.
+/// Returned by `GET /game/:id/state` and sent when a player reconnects.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct GameSnapshot {
+    pub players: Vec<String>,
+    // Seat of the player this snapshot is for
+    pub seat: u8,
+    // Team of the player sitting at each seat
+    pub teams: Vec<u8>,
+    pub started: bool,
+    pub finished: bool,
+    // Cards in the player's hand
+    pub cards: Vec<Card>,
+    // Number of cards held by the player sitting at each seat
+    pub hand_sizes: Vec<u8>,
+    // Cards left to be dealt, the trump card included
+    pub deck: u8,
+    pub trump: Card,
+    // Cards played so far this round
+    pub played: Vec<Card>,
+    // Cards of the rounds already played this game
+    pub seen: Vec<Card>,
+    // Points of each team in the current game
+    pub score: Vec<u8>,
+    pub turn: u8,
+    pub round: u8,
+    pub declared: Vec<Suit>,
+    // Trump cards already taken in exchange this game
+    pub exchanged: Vec<Card>,
+    // Number of the current game in the match, and games won by each team
+    pub hand: u8,
+    pub wins: Vec<u8>,
+    // Deadline of the current turn, in milliseconds since the Unix epoch
+    pub deadline: Option<u64>,
+    // Hash of the server seed of the next deck, while the player's seed is awaited
+    pub seed_request: Option<String>
+}
+
+/// Seeds a game's deck was shuffled with, revealed when the game ends.
+/// See `fair::verify` to check them against the commitments sent before the game started.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Reveal {
+    pub server_seed: String,
+    // Seed sent for this deck by the player sitting at each seat
+    pub client_seeds: Vec<String>
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum GameResult {
+    // Players of the team(s) sharing the highest score
+    Winners(Vec<String>),
+    // Every team finished with the same score
+    Draw,
+    // A player left and the game was called off
+    Cancelled,
+    // Nobody played for too long and the game was called off
+    Abandoned
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Departure {
+    // Left the lobby before the game started
+    Left,
+    Forfeited,
+    // Lost their connection and did not come back in time
+    Disconnected,
+    // Ran out of time to play their turn
+    TimedOut
+}
+
+/// What happens to a game in progress when a player forfeits or disconnects.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum LeavePolicy {
+    // The teams of the other players win the match
+    #[default]
+    OpponentsWin,
+    // A bot plays the cards of the player that left
+    Bot,
+    // The match ends without a winner
+    Cancel
+}
+
+/// What happens when a player runs out of time to play their turn.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum TimeoutPolicy {
+    // The first card of their hand is played for them
+    #[default]
+    AutoPlay,
+    // The player forfeits the game, which is then resolved by the LeavePolicy
+    Forfeit
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub num_players: u8,
+    pub rules: rules::Rules,
+    // Number of games a team must win to win the match
+    pub match_length: u8,
+    // Hidden from the lobby and only joinable with the invite code
+    pub private: bool,
+    pub on_leave: LeavePolicy,
+    // Seconds each player has to play their turn. No limit if not set
+    pub turn_seconds: Option<u32>,
+    pub on_timeout: TimeoutPolicy,
+    // Seats filled with bots when the game is created. At least one seat is left for a player
+    pub bots: u8,
+    // Strength of the bots filling seats or replacing players who left
+    pub bot_level: bot::BotLevel,
+    // Seed of the server's part of the deck shuffles, to replay a match. Random if not set.
+    // Only shown in the game info once the game is over
+    pub seed: Option<u64>
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            num_players: 2,
+            rules: rules::Rules::default(),
+            match_length: 1,
+            private: false,
+            on_leave: LeavePolicy::default(),
+            turn_seconds: None,
+            on_timeout: TimeoutPolicy::default(),
+            bots: 0,
+            bot_level: bot::BotLevel::default(),
+            seed: None
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewGame {
+    pub id: String,
+    // Code to share with the invited players of a private game
+    pub invite: Option<String>
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum GameStatus {
+    // Waiting for players to join
+    #[default]
+    Waiting,
+    InProgress,
+    Finished,
+    // Nobody played for too long. The game can no longer be joined or played
+    Abandoned,
+    // A player left and the game was called off
+    Cancelled
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct GameInfo {
     pub id: String,
-    pub num_players: u8
-}
\ No newline at end of file
+    pub num_players: u8,
+    pub config: GameConfig,
+    // Players that have joined, in seat order
+    pub players: Vec<String>,
+    // Players that gave up their seat for good and cannot rejoin
+    pub left: Vec<String>,
+    // Seconds since the Unix epoch
+    pub created: u64,
+    pub status: GameStatus
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusFilter {
+    #[default]
+    Waiting,
+    Ongoing,
+    // Over, whether played out, cancelled or abandoned
+    Finished,
+    All
+}
+
+impl StatusFilter {
+    pub fn matches(&self, status: &GameStatus) -> bool {
+        match self {
+            StatusFilter::Waiting => *status == GameStatus::Waiting,
+            StatusFilter::Ongoing => *status == GameStatus::InProgress,
+            StatusFilter::Finished => matches!(status, GameStatus::Finished | GameStatus::Cancelled | GameStatus::Abandoned),
+            StatusFilter::All => true
+        }
+    }
+}
+
+/// Query string of `GET /game`. Only public games are listed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct GameQuery {
+    pub status: StatusFilter,
+    pub num_players: Option<u8>,
+    pub variant: Option<rules::Variant>,
+    // Number of games to skip and maximum number of games to return, oldest games first
+    pub offset: usize,
+    pub limit: usize
+}
+
+impl Default for GameQuery {
+    fn default() -> GameQuery {
+        GameQuery {
+            status: StatusFilter::Waiting,
+            num_players: None,
+            variant: None,
+            offset: 0,
+            limit: 50
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Credentials {
+    pub player_id: String,
+    pub password: String
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Session {
+    pub player_id: String,
+    // Sent as `authorization: Bearer <token>` on every game request
+    pub token: String
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ErrorCode {
+    Unauthorized,
+    InvalidCredentials,
+    PlayerExists,
+    UnsupportedPlayers,
+    InvalidConfig,
+    GameNotFound,
+    InvalidInvite,
+    InvalidSeed,
+    PlayerNotFound,
+    PlayerLeft,
+    GameFull,
+    GameInProgress,
+    GameNotStarted,
+    GameFinished,
+    NotYourTurn,
+    CardNotInHand,
+    CannotExchangeTrump,
+    CannotDeclare,
+    SeedNotRequested,
+    // The path does not exist
+    NotFound,
+    // The body, query string or headers could not be read
+    InvalidRequest,
+    ServerError
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode) -> ErrorResponse {
+        let message = match code {
+            ErrorCode::Unauthorized => "A valid session token is required",
+            ErrorCode::InvalidCredentials => "The player id or password is not valid",
+            ErrorCode::PlayerExists => "A player with that id is already registered",
+            ErrorCode::UnsupportedPlayers => "The rules do not support that number of players",
+            ErrorCode::InvalidConfig => "The game configuration is not valid",
+            ErrorCode::GameNotFound => "The game does not exist",
+            ErrorCode::InvalidInvite => "The game is private and the invite code is not valid",
+            ErrorCode::InvalidSeed => "The seed is too long",
+            ErrorCode::PlayerNotFound => "The player is not part of this game",
+            ErrorCode::PlayerLeft => "The player has left this game and cannot rejoin it",
+            ErrorCode::GameFull => "The game is full or has already started",
+            ErrorCode::GameInProgress => "The game has already started. Forfeit it instead",
+            ErrorCode::GameNotStarted => "The game has not started yet",
+            ErrorCode::GameFinished => "The game has already finished",
+            ErrorCode::NotYourTurn => "It is not this player's turn",
+            ErrorCode::CardNotInHand => "The card is not in the player's hand",
+            ErrorCode::CannotExchangeTrump => "The player cannot exchange the trump card",
+            ErrorCode::CannotDeclare => "The player cannot declare that suit",
+            ErrorCode::SeedNotRequested => "The player's seed is not awaited",
+            ErrorCode::NotFound => "There is nothing at that path",
+            ErrorCode::InvalidRequest => "The request could not be read",
+            ErrorCode::ServerError => "The server could not complete the request"
+        };
+        ErrorResponse { code, message: message.to_string() }
+    }
+}
+
+impl From<rules::RuleError> for ErrorResponse {
+    fn from(error: rules::RuleError) -> ErrorResponse {
+        ErrorResponse::new(match error {
+            rules::RuleError::UnsupportedPlayers => ErrorCode::UnsupportedPlayers,
+            rules::RuleError::NotStarted => ErrorCode::GameNotStarted,
+            rules::RuleError::AlreadyStarted => ErrorCode::GameFull,
+            rules::RuleError::Finished => ErrorCode::GameFinished,
+            rules::RuleError::UnknownSeat => ErrorCode::PlayerNotFound,
+            rules::RuleError::NotYourTurn => ErrorCode::NotYourTurn,
+            rules::RuleError::CardNotInHand => ErrorCode::CardNotInHand,
+            rules::RuleError::CannotExchangeTrump => ErrorCode::CannotExchangeTrump,
+            rules::RuleError::CannotDeclare => ErrorCode::CannotDeclare
+        })
+    }
+}
+
+impl From<ErrorCode> for ErrorResponse {
+    fn from(code: ErrorCode) -> ErrorResponse {
+        ErrorResponse::new(code)
+    }
+}
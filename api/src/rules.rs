@@ -0,0 +1,1050 @@
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use crate::{fair, Card, Reveal, Suit};
+
+
+// Deck of cards
+pub static CARDS: [Card; 40] = [
+    Card {number: 1, suit: Suit::Coin},
+    Card {number: 2, suit: Suit::Coin},
+    Card {number: 3, suit: Suit::Coin},
+    Card {number: 4, suit: Suit::Coin},
+    Card {number: 5, suit: Suit::Coin},
+    Card {number: 6, suit: Suit::Coin},
+    Card {number: 7, suit: Suit::Coin},
+    Card {number: 10, suit: Suit::Coin},
+    Card {number: 11, suit: Suit::Coin},
+    Card {number: 12, suit: Suit::Coin},
+    Card {number: 1, suit: Suit::Cup},
+    Card {number: 2, suit: Suit::Cup},
+    Card {number: 3, suit: Suit::Cup},
+    Card {number: 4, suit: Suit::Cup},
+    Card {number: 5, suit: Suit::Cup},
+    Card {number: 6, suit: Suit::Cup},
+    Card {number: 7, suit: Suit::Cup},
+    Card {number: 10, suit: Suit::Cup},
+    Card {number: 11, suit: Suit::Cup},
+    Card {number: 12, suit: Suit::Cup},
+    Card {number: 1, suit: Suit::Baton},
+    Card {number: 2, suit: Suit::Baton},
+    Card {number: 3, suit: Suit::Baton},
+    Card {number: 4, suit: Suit::Baton},
+    Card {number: 5, suit: Suit::Baton},
+    Card {number: 6, suit: Suit::Baton},
+    Card {number: 7, suit: Suit::Baton},
+    Card {number: 10, suit: Suit::Baton},
+    Card {number: 11, suit: Suit::Baton},
+    Card {number: 12, suit: Suit::Baton},
+    Card {number: 1, suit: Suit::Sword},
+    Card {number: 2, suit: Suit::Sword},
+    Card {number: 3, suit: Suit::Sword},
+    Card {number: 4, suit: Suit::Sword},
+    Card {number: 5, suit: Suit::Sword},
+    Card {number: 6, suit: Suit::Sword},
+    Card {number: 7, suit: Suit::Sword},
+    Card {number: 10, suit: Suit::Sword},
+    Card {number: 11, suit: Suit::Sword},
+    Card {number: 12, suit: Suit::Sword},
+];
+
+// Number of players the rules support
+pub static NUM_PLAYERS: [u8; 3] = [2, 3, 4];
+
+// Card removed from the deck in 3 player games, so that it can be dealt evenly
+static REMOVED_FOR_3_PLAYERS: Card = Card {number: 2, suit: Suit::Coin};
+
+/// Unshuffled deck of cards for a game of `num_players`.
+pub fn deck(num_players: u8) -> Vec<Card> {
+    let mut deck = CARDS.to_vec();
+    if num_players == 3 {
+        deck.retain(|card| *card != REMOVED_FOR_3_PLAYERS);
+    }
+    deck
+}
+
+// Look-up table of points values awarded for each card number value
+// Map to the following numbers [x, 1,  2,  3, 4, 5, 6, 7, x, x, 10, 11, 12]
+static POINTS_PER_CARD: [u8; 13] = [0, 11, 0, 10, 0, 0, 0, 0, 0, 0,  2,  3,  4];
+// Look-up table of heirarchical ordering 0..10 based on points and number
+static ORDER: [u8; 13] = [0, 9, 0, 8, 1, 2, 3, 4, 0, 0, 5, 6, 7];
+
+/// Points awarded to the winner of a round for this card.
+pub fn points(card: &Card) -> u8 {
+    POINTS_PER_CARD[card.number as usize]
+}
+
+/// Strength of the card within its suit. Higher beats lower.
+pub fn rank(card: &Card) -> u8 {
+    ORDER[card.number as usize]
+}
+
+/// Index of the winning card within a round's played cards.
+pub fn round_winner(played: &[Card], trump: &Suit) -> usize {
+    let mut winner = 0;
+    // First card played dictates the round's winning suit, unless another player plays card with the trump suit
+    let mut best_card = &played[0];
+
+    for (i, card) in played.iter().enumerate().skip(1) {
+        if card.suit == best_card.suit {
+            if rank(card) > rank(best_card) {
+                best_card = card;
+                winner = i;
+            }
+        } else if card.suit == *trump {
+            best_card = card;
+            winner = i;
+        }
+    }
+    winner
+}
+
+/// Card of the hand that can be swapped for the trump card, if any, with `deck` cards left to be dealt,
+/// the trump card included. The 2 of trumps can be exchanged while the trump card is still in the deck,
+/// the 7 of trumps only once the deck is down to the last deal. Either must be outranked by the trump card.
+pub fn trump_exchange_card(rules: &Rules, num_players: u8, hand: &[Card], trump: &Card, deck: usize) -> Option<Card> {
+    if !rules.trump_exchange || deck == 0 {
+        // The trump card has already been dealt
+        return None;
+    }
+    hand.iter()
+        .filter(|card| card.suit == trump.suit && rank(card) < rank(trump))
+        .find(|card| card.number == 2 || (card.number == 7 && deck <= num_players as usize))
+        .cloned()
+}
+
+/// Suits of the hand that can be declared in the given round, by a player about to lead it.
+/// Only the winner of the previous round can declare, before leading the next one.
+/// Each suit can only be declared once per game.
+pub fn declarable_suits(rules: &Rules, hand: &[Card], declared: &[Suit], round: u8, leading: bool) -> Vec<Suit> {
+    if !rules.declarations || !leading || round <= 1 {
+        return Vec::new();
+    }
+    [Suit::Coin, Suit::Cup, Suit::Baton, Suit::Sword]
+        .into_iter()
+        .filter(|suit| !declared.contains(suit))
+        .filter(|suit| [11, 12].iter().all(|&number| hand.contains(&Card {number, suit: suit.clone()})))
+        .collect()
+}
+
+// Points for declaring the king and horse of a suit, and of the trump suit
+static DECLARATION_POINTS: u8 = 20;
+static TRUMP_DECLARATION_POINTS: u8 = 40;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum Variant {
+    // Hands of 3 cards
+    #[default]
+    Classic,
+    // Brisca a 2 cartas: hands of 2 cards
+    TwoCards
+}
+
+/// Optional rules chosen when the game is created.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Rules {
+    pub variant: Variant,
+    // Partners sitting across play as a team. Only for 4 players
+    pub teams: bool,
+    // The 2 (or the 7) of trumps can be swapped for the trump card
+    pub trump_exchange: bool,
+    // Holding the king and horse of a suit can be declared for bonus points
+    pub declarations: bool,
+    // Points that win the game as soon as a team reaches them.
+    // Otherwise all cards are played and the highest score wins
+    pub winning_score: Option<u8>
+}
+
+impl Rules {
+    pub fn hand_size(&self) -> usize {
+        match self.variant {
+            Variant::Classic => 3,
+            Variant::TwoCards => 2
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Phase {
+    #[default]
+    Waiting,
+    Playing,
+    Finished
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Action {
+    // Seat of the player and the card played
+    Play(u8, Card),
+    // Seat of the player swapping the 2 (or the 7) of trumps for the trump card
+    ExchangeTrump(u8),
+    // Seat of the player declaring the king and horse of the suit
+    Declare(u8, Suit)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // Card dealt to the player sitting at seat
+    Dealt(u8, Card),
+    // All hands dealt. Carries the trump card and the seat playing first
+    Started(Card, u8),
+    // Card played by the player sitting at seat
+    Played(u8, Card),
+    // Seat that took the trump card and the card that replaced it as trump
+    TrumpExchanged(u8, Card),
+    // Seat that declared the suit, the bonus points and the updated team totals
+    Declared(u8, Suit, u8, Vec<u8>),
+    // Trick of the round that has just ended and the updated team totals
+    RoundEnd(Trick, Vec<u8>),
+    // Result of the game once all cards have been played and the final team totals
+    GameEnd(Outcome, Vec<u8>),
+    // Games won by each team so far in the match
+    MatchScore(Vec<u8>),
+    // Result of the match and the games won by each team
+    MatchEnd(Outcome, Vec<u8>)
+}
+
+/// Cards played in a round and who collected them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Trick {
+    pub round: u8,
+    // Seat of the player and the card played, in playing order
+    pub cards: Vec<(u8, Card)>,
+    pub winner: u8,
+    // Points collected by the winner
+    pub points: u8,
+    // Cards left in the deck once the players have drawn, the trump card included
+    pub deck: u8
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // Seats of the team(s) sharing the highest score
+    Winners(Vec<u8>),
+    // Every team finished with the same score
+    Draw
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleError {
+    UnsupportedPlayers,
+    NotStarted,
+    AlreadyStarted,
+    Finished,
+    UnknownSeat,
+    NotYourTurn,
+    CardNotInHand,
+    CannotExchangeTrump,
+    CannotDeclare
+}
+
+impl std::fmt::Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            RuleError::UnsupportedPlayers => "The rules do not support that number of players",
+            RuleError::NotStarted => "The game has not started yet",
+            RuleError::AlreadyStarted => "The game has already started",
+            RuleError::Finished => "The game has already finished",
+            RuleError::UnknownSeat => "There is no player sitting at that seat",
+            RuleError::NotYourTurn => "It is not this player's turn",
+            RuleError::CardNotInHand => "The card is not in the player's hand",
+            RuleError::CannotExchangeTrump => "The player cannot exchange the trump card",
+            RuleError::CannotDeclare => "The player cannot declare that suit"
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Rules of a single game of Brisca, independent of how players are connected.
+#[derive(Clone, Debug, Default)]
+pub struct GameState {
+    pub num_players: u8,
+    pub rules: Rules,
+    pub phase: Phase,
+    pub deck: Vec<Card>,
+    // Team of the player sitting at each seat
+    pub teams: Vec<u8>,
+    pub hands: Vec<Vec<Card>>,
+    pub played: Vec<Card>,
+    pub scores: Vec<u8>,
+    pub turn: u8,
+    pub round: u8,
+    pub trump: Card,
+    // Suits already declared this game
+    pub declared: Vec<Suit>,
+    // Trump cards already taken in exchange this game
+    pub exchanged: Vec<Card>,
+    // Cards of the rounds already played this game
+    pub seen: Vec<Card>
+}
+
+impl GameState {
+    /// Every player plays for themselves unless playing in teams, in which case
+    /// partners sit across from each other: seats 0 and 2 against seats 1 and 3.
+    pub fn new(num_players: u8, rules: Rules) -> Result<GameState, RuleError> {
+        if !NUM_PLAYERS.contains(&num_players) || (rules.teams && num_players != 4) {
+            return Err(RuleError::UnsupportedPlayers);
+        }
+        Ok(GameState {
+            num_players,
+            teams: (0..num_players).map(|seat| if rules.teams { seat % 2 } else { seat }).collect(),
+            rules,
+            hands: vec![Vec::new(); num_players as usize],
+            scores: vec![0; num_players as usize],
+            ..Default::default()
+        })
+    }
+
+    /// Deal a shuffled deck and start the game with the player sitting at `first`.
+    /// The last card of the deck is the first one to be dealt.
+    pub fn start(&mut self, deck: Vec<Card>, first: u8) -> Result<Vec<Event>, RuleError> {
+        if self.phase != Phase::Waiting {
+            return Err(RuleError::AlreadyStarted);
+        }
+        if !deck.len().is_multiple_of(self.num_players as usize) {
+            // Every player must get the same number of cards
+            return Err(RuleError::UnsupportedPlayers);
+        }
+        let mut events = Vec::new();
+
+        self.deck = deck;
+        // Get the trump card from the top of the deck
+        // The trump card should stay in the deck as the last card to be dealt
+        self.trump = self.deck[0].clone();
+
+        for i in 0..self.num_players {
+            // Deal a full hand to each player. Starting from the first player
+            let seat = (first + i) % self.num_players;
+            for _ in 0..self.rules.hand_size() {
+                let card = self.deck.pop().unwrap();
+                self.hands[seat as usize].push(card.clone());
+                events.push(Event::Dealt(seat, card));
+            }
+        }
+        events.push(Event::Started(self.trump.clone(), first));
+
+        self.turn = first;
+        self.phase = Phase::Playing;
+        self.round = 1;
+        Ok(events)
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<Vec<Event>, RuleError> {
+        match action {
+            Action::Play(seat, card) => self.play(seat, card),
+            Action::ExchangeTrump(seat) => self.exchange_trump(seat),
+            Action::Declare(seat, suit) => self.declare(seat, suit)
+        }
+    }
+
+    // Check that the player sitting at seat can act now
+    fn check_turn(&self, seat: u8) -> Result<(), RuleError> {
+        match self.phase {
+            Phase::Waiting => return Err(RuleError::NotStarted),
+            Phase::Finished => return Err(RuleError::Finished),
+            Phase::Playing => {}
+        }
+        if seat >= self.num_players {
+            return Err(RuleError::UnknownSeat);
+        }
+        if seat != self.turn {
+            return Err(RuleError::NotYourTurn);
+        }
+        Ok(())
+    }
+
+    /// Card of the player's hand that can be swapped for the trump card, if any.
+    /// Each trump card can only be taken once.
+    pub fn trump_exchange_card(&self, seat: u8) -> Option<Card> {
+        if self.exchanged.contains(&self.trump) {
+            return None;
+        }
+        trump_exchange_card(&self.rules, self.num_players, self.hands.get(seat as usize)?, &self.trump, self.deck.len())
+    }
+
+    fn exchange_trump(&mut self, seat: u8) -> Result<Vec<Event>, RuleError> {
+        self.check_turn(seat)?;
+        let Some(card) = self.trump_exchange_card(seat) else {
+            return Err(RuleError::CannotExchangeTrump);
+        };
+
+        // Swap the card in the player's hand with the trump card at the bottom of the deck
+        let hand = &mut self.hands[seat as usize];
+        hand.retain(|c| *c != card);
+        hand.push(self.trump.clone());
+        self.exchanged.push(self.trump.clone());
+        self.deck[0] = card.clone();
+        self.trump = card.clone();
+
+        Ok(vec![Event::TrumpExchanged(seat, card)])
+    }
+
+    /// Suits the player can declare right now.
+    pub fn declarable_suits(&self, seat: u8) -> Vec<Suit> {
+        if self.check_turn(seat).is_err() {
+            return Vec::new();
+        }
+        declarable_suits(&self.rules, &self.hands[seat as usize], &self.declared, self.round, self.played.is_empty())
+    }
+
+    fn declare(&mut self, seat: u8, suit: Suit) -> Result<Vec<Event>, RuleError> {
+        self.check_turn(seat)?;
+        if !self.declarable_suits(seat).contains(&suit) {
+            return Err(RuleError::CannotDeclare);
+        }
+
+        let points = if suit == self.trump.suit { TRUMP_DECLARATION_POINTS } else { DECLARATION_POINTS };
+        self.scores[seat as usize] += points;
+        self.declared.push(suit.clone());
+
+        let mut events = vec![Event::Declared(seat, suit, points, self.team_scores())];
+        if self.reached_winning_score() {
+            self.phase = Phase::Finished;
+            events.push(Event::GameEnd(self.outcome(), self.team_scores()));
+        }
+        Ok(events)
+    }
+
+    fn play(&mut self, seat: u8, card: Card) -> Result<Vec<Event>, RuleError> {
+        self.check_turn(seat)?;
+        let hand = &mut self.hands[seat as usize];
+        let Some(card_idx) = hand.iter().position(|c| *c == card) else {
+            return Err(RuleError::CardNotInHand);
+        };
+
+        // Remove card from player's hand
+        hand.remove(card_idx);
+
+        let mut events = vec![Event::Played(seat, card.clone())];
+
+        // Save card played
+        self.played.push(card);
+
+        // Check if all players have played
+        if self.played.len() < self.num_players as usize {
+            // Advance to next player's turn
+            self.turn = (self.turn + 1) % self.num_players;
+            return Ok(events);
+        }
+
+        // End of the round. Compute result.
+        let score = self.played.iter().map(points).sum();
+        let winner_idx = round_winner(&self.played, &self.trump.suit) as u8;
+
+        // Shift based on which player started the round (turn + 1)
+        let first = (self.turn + 1) % self.num_players;
+        let winner = (winner_idx + first) % self.num_players;
+
+        // Update player score
+        self.scores[winner as usize] += score;
+
+        let mut trick = Trick {
+            round: self.round,
+            cards: self.played
+                .drain(..)
+                .enumerate()
+                .map(|(i, card)| ((first + i as u8) % self.num_players, card))
+                .collect(),
+            winner,
+            points: score,
+            deck: 0
+        };
+        self.seen.extend(trick.cards.iter().map(|(_, card)| card.clone()));
+
+        // Update who plays first turn in next round based on the winner
+        self.turn = winner;
+
+        // Update round counter
+        self.round += 1;
+
+        // Check if a team has already won or there's more cards.
+        let mut dealt = Vec::new();
+        let mut finished = false;
+        if self.reached_winning_score() {
+            finished = true;
+        } else if !self.deck.is_empty() {
+            // Deal new card to all players. Starting from the round winner
+            for i in 0..self.num_players {
+                let card = self.deck.pop().unwrap();
+                let seat = (winner + i) % self.num_players;
+                self.hands[seat as usize].push(card.clone());
+                dealt.push(Event::Dealt(seat, card));
+            }
+        } else if self.hands.iter().all(|hand| hand.is_empty()) {
+            // We've reached the last round of the game
+            finished = true;
+        }
+
+        trick.deck = self.deck.len() as u8;
+        events.push(Event::RoundEnd(trick, self.team_scores()));
+        events.extend(dealt);
+        if finished {
+            self.phase = Phase::Finished;
+
+            events.push(Event::GameEnd(self.outcome(), self.team_scores()));
+        }
+        Ok(events)
+    }
+
+    fn reached_winning_score(&self) -> bool {
+        self.rules.winning_score.is_some_and(|winning_score| self.team_scores().iter().any(|&total| total >= winning_score))
+    }
+
+    pub fn num_teams(&self) -> u8 {
+        self.teams.iter().max().map_or(0, |team| team + 1)
+    }
+
+    /// Seat of the player on the same team, if playing in teams.
+    pub fn partner(&self, seat: u8) -> Option<u8> {
+        (0..self.num_players).find(|&other| other != seat && self.teams[other as usize] == self.teams[seat as usize])
+    }
+
+    /// Points collected by each team, indexed by team.
+    pub fn team_scores(&self) -> Vec<u8> {
+        let mut totals = vec![0; self.num_teams() as usize];
+        for (seat, score) in self.scores.iter().enumerate() {
+            totals[self.teams[seat] as usize] += score;
+        }
+        totals
+    }
+
+    /// Players of the team(s) with the highest score. A draw if every team is tied.
+    pub fn outcome(&self) -> Outcome {
+        self.outcome_of(&self.team_scores())
+    }
+
+    /// Players of the team(s) with the highest of the given totals, indexed by team.
+    pub fn outcome_of(&self, totals: &[u8]) -> Outcome {
+        let max_score = totals.iter().max().copied().unwrap_or_default();
+        let winners: Vec<u8> = (0..self.num_players)
+            .filter(|&seat| totals[self.teams[seat as usize] as usize] == max_score)
+            .collect();
+
+        if totals.iter().all(|&total| total == max_score) {
+            Outcome::Draw
+        } else {
+            Outcome::Winners(winners)
+        }
+    }
+}
+
+/// A series of games between the same players, re-dealt until a team wins `match_length` of them.
+/// The dealer rotates every game and the player after the dealer plays first.
+#[derive(Clone, Debug)]
+pub struct MatchState {
+    pub match_length: u8,
+    // Game currently being played
+    pub game: GameState,
+    // Games won by each team
+    pub wins: Vec<u8>,
+    // Number of the current game, starting at 1
+    pub hand: u8,
+    pub dealer: u8,
+    // Team that gave up the match
+    pub forfeited: Option<u8>,
+    // Seed of the generator of the server seeds of every game. The same seeds deal the same decks.
+    // ChaCha20 generates the same numbers from a seed on every platform and version of rand
+    pub seed: u64,
+    rng: ChaCha20Rng,
+    // Server seed of the next deck, drawn before the players send their seeds
+    next_server_seed: String,
+    // Seeds of the current game's deck, kept secret until the game ends
+    pub server_seed: String,
+    pub client_seeds: Vec<String>,
+    // Hash of the server seed and the deck, published when the game starts
+    pub commitment: String
+}
+
+impl MatchState {
+    pub fn new(num_players: u8, rules: Rules, match_length: u8, seed: u64) -> Result<MatchState, RuleError> {
+        let game = GameState::new(num_players, rules)?;
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        let next_server_seed = draw_server_seed(&mut rng);
+        Ok(MatchState {
+            match_length,
+            wins: vec![0; game.num_teams() as usize],
+            game,
+            hand: 0,
+            // So that the player at seat 0 plays first in the first game
+            dealer: num_players - 1,
+            forfeited: None,
+            seed,
+            rng,
+            next_server_seed,
+            server_seed: String::new(),
+            client_seeds: Vec::new(),
+            commitment: String::new()
+        })
+    }
+
+    pub fn num_players(&self) -> u8 {
+        self.game.num_players
+    }
+
+    /// Shuffle a new deck with the seeds of the players sitting at each seat and deal it for the next game of the match.
+    pub fn deal(&mut self, client_seeds: &[String]) -> Result<Vec<Event>, RuleError> {
+        if self.is_finished() {
+            return Err(RuleError::Finished);
+        }
+        if self.hand > 0 {
+            if self.game.phase != Phase::Finished {
+                return Err(RuleError::AlreadyStarted);
+            }
+            // Rotate the dealer and start a new game with the same rules
+            self.dealer = (self.dealer + 1) % self.num_players();
+            self.game = GameState::new(self.num_players(), self.game.rules.clone())?;
+        }
+        self.hand += 1;
+
+        let next_server_seed = draw_server_seed(&mut self.rng);
+        self.server_seed = std::mem::replace(&mut self.next_server_seed, next_server_seed);
+        self.client_seeds = client_seeds.to_vec();
+        let deck = fair::shuffle(self.num_players(), &self.server_seed, &self.client_seeds);
+        self.commitment = fair::commitment(&self.server_seed, &deck);
+
+        let first = (self.dealer + 1) % self.num_players();
+        self.game.start(deck, first)
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<Vec<Event>, RuleError> {
+        let mut events = self.game.apply(action)?;
+
+        if self.game.phase == Phase::Finished {
+            // Award the game to the winning team(s)
+            if let Outcome::Winners(seats) = self.game.outcome() {
+                let mut teams: Vec<u8> = seats.iter().map(|&seat| self.game.teams[seat as usize]).collect();
+                teams.sort();
+                teams.dedup();
+                for team in teams {
+                    self.wins[team as usize] += 1;
+                }
+            }
+            events.push(Event::MatchScore(self.wins.clone()));
+
+            if self.is_finished() {
+                events.push(Event::MatchEnd(self.game.outcome_of(&self.wins), self.wins.clone()));
+            }
+        }
+        Ok(events)
+    }
+
+    /// The player sitting at seat gives up, possibly before the first deal. Every other team wins
+    /// the game being played, if any, and the match.
+    pub fn forfeit(&mut self, seat: u8) -> Result<Vec<Event>, RuleError> {
+        if self.is_finished() {
+            return Err(RuleError::Finished);
+        }
+        if seat >= self.num_players() {
+            return Err(RuleError::UnknownSeat);
+        }
+        let team = self.game.teams[seat as usize];
+        let winners: Vec<u8> = (0..self.num_players())
+            .filter(|&other| self.game.teams[other as usize] != team)
+            .collect();
+        self.forfeited = Some(team);
+
+        let mut events = Vec::new();
+        if self.hand > 0 && self.game.phase != Phase::Finished {
+            self.game.phase = Phase::Finished;
+            events.push(Event::GameEnd(Outcome::Winners(winners.clone()), self.game.team_scores()));
+        }
+        events.push(Event::MatchEnd(Outcome::Winners(winners), self.wins.clone()));
+        Ok(events)
+    }
+
+    /// Hash of the server seed of the next deck, to publish before the players send their seeds.
+    pub fn seed_commitment(&self) -> String {
+        fair::seed_hash(&self.next_server_seed)
+    }
+
+    /// Seeds of the current game's deck. Only to be shown once the game has ended.
+    pub fn reveal(&self) -> Reveal {
+        Reveal { server_seed: self.server_seed.clone(), client_seeds: self.client_seeds.clone() }
+    }
+
+    /// The match has not started yet, or its current game has finished and the match goes on,
+    /// so a new deck must be dealt.
+    pub fn needs_deal(&self) -> bool {
+        (self.hand == 0 || self.game.phase == Phase::Finished) && !self.is_finished()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.forfeited.is_some() || self.wins.iter().any(|&wins| wins >= self.match_length)
+    }
+}
+
+fn draw_server_seed(rng: &mut ChaCha20Rng) -> String {
+    let mut seed = [0; 32];
+    rng.fill_bytes(&mut seed);
+    hex::encode(seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(number: u8, suit: Suit) -> Card {
+        Card { number, suit }
+    }
+
+    // Game of 2 players dealt a short deck, seat 1 playing first. Cups are trumps.
+    // Seat 1 is dealt 11 of coins, 2 of cups and 5 of swords; seat 0 the ace of swords,
+    // 12 of coins and 3 of swords; seat 0 then draws the 4 of coins and seat 1 the trump card
+    fn short_game() -> (GameState, Vec<Event>) {
+        let deck = vec![
+            card(7, Suit::Cup),
+            card(4, Suit::Coin),
+            card(3, Suit::Sword),
+            card(12, Suit::Coin),
+            card(1, Suit::Sword),
+            card(5, Suit::Sword),
+            card(2, Suit::Cup),
+            card(11, Suit::Coin)
+        ];
+        let mut state = GameState::new(2, Rules::default()).unwrap();
+        let events = state.start(deck, 1).unwrap();
+        (state, events)
+    }
+
+    #[test]
+    fn trump_wins_the_round() {
+        let played = [card(1, Suit::Coin), card(3, Suit::Coin), card(2, Suit::Cup)];
+        assert_eq!(round_winner(&played, &Suit::Cup), 2);
+    }
+
+    #[test]
+    fn highest_card_of_the_led_suit_wins_the_round() {
+        let played = [card(3, Suit::Coin), card(1, Suit::Coin), card(12, Suit::Sword), card(7, Suit::Coin)];
+        assert_eq!(round_winner(&played, &Suit::Cup), 1);
+        // Nobody follows the led suit, nor plays a trump
+        let played = [card(4, Suit::Baton), card(1, Suit::Coin), card(3, Suit::Sword)];
+        assert_eq!(round_winner(&played, &Suit::Cup), 0);
+    }
+
+    #[test]
+    fn deals_from_the_last_card_starting_with_the_first_player() {
+        let (state, events) = short_game();
+        assert_eq!(state.hands[1], vec![card(11, Suit::Coin), card(2, Suit::Cup), card(5, Suit::Sword)]);
+        assert_eq!(state.hands[0], vec![card(1, Suit::Sword), card(12, Suit::Coin), card(3, Suit::Sword)]);
+        assert_eq!(state.trump, card(7, Suit::Cup));
+        assert_eq!(state.deck.len(), 2);
+        assert_eq!(state.turn, 1);
+        assert_eq!(events.first(), Some(&Event::Dealt(1, card(11, Suit::Coin))));
+        assert_eq!(events.last(), Some(&Event::Started(card(7, Suit::Cup), 1)));
+    }
+
+    #[test]
+    fn winner_of_the_round_collects_the_points_and_leads_the_next_one() {
+        let (mut state, _) = short_game();
+        state.apply(Action::Play(1, card(5, Suit::Sword))).unwrap();
+        let events = state.apply(Action::Play(0, card(1, Suit::Sword))).unwrap();
+
+        let trick = Trick {
+            round: 1,
+            cards: vec![(1, card(5, Suit::Sword)), (0, card(1, Suit::Sword))],
+            winner: 0,
+            points: 11,
+            deck: 0
+        };
+        assert_eq!(events, vec![
+            Event::Played(0, card(1, Suit::Sword)),
+            Event::RoundEnd(trick, vec![11, 0]),
+            // The winner draws first
+            Event::Dealt(0, card(4, Suit::Coin)),
+            Event::Dealt(1, card(7, Suit::Cup))
+        ]);
+        assert_eq!(state.turn, 0);
+        assert_eq!(state.round, 2);
+    }
+
+    #[test]
+    fn game_ends_once_the_deck_and_hands_are_empty() {
+        let (mut state, _) = short_game();
+        let plays = [
+            (1, card(5, Suit::Sword)), (0, card(1, Suit::Sword)),
+            (0, card(12, Suit::Coin)), (1, card(2, Suit::Cup)),
+            (1, card(11, Suit::Coin)), (0, card(4, Suit::Coin)),
+            (1, card(7, Suit::Cup))
+        ];
+        for (seat, card) in plays {
+            state.apply(Action::Play(seat, card)).unwrap();
+            assert_eq!(state.phase, Phase::Playing);
+        }
+        let events = state.apply(Action::Play(0, card(3, Suit::Sword))).unwrap();
+
+        assert_eq!(state.phase, Phase::Finished);
+        assert!(state.deck.is_empty() && state.hands.iter().all(Vec::is_empty));
+        assert_eq!(events.last(), Some(&Event::GameEnd(Outcome::Winners(vec![1]), vec![11, 17])));
+        assert_eq!(state.apply(Action::Play(1, card(3, Suit::Sword))), Err(RuleError::Finished));
+    }
+
+    #[test]
+    fn trump_card_is_only_exchanged_for_a_lower_card() {
+        // Seat 1 is dealt the 7 and the 2 of cups. The 12 of cups is the trump card, 2 cards are left
+        let deck = vec![
+            card(12, Suit::Cup),
+            card(4, Suit::Coin),
+            card(3, Suit::Sword),
+            card(1, Suit::Sword),
+            card(12, Suit::Coin),
+            card(5, Suit::Sword),
+            card(2, Suit::Cup),
+            card(7, Suit::Cup)
+        ];
+        let rules = Rules { trump_exchange: true, ..Default::default() };
+        let mut state = GameState::new(2, rules).unwrap();
+        state.start(deck, 1).unwrap();
+
+        assert_eq!(state.apply(Action::ExchangeTrump(1)), Ok(vec![Event::TrumpExchanged(1, card(7, Suit::Cup))]));
+        assert_eq!(state.apply(Action::ExchangeTrump(1)), Ok(vec![Event::TrumpExchanged(1, card(2, Suit::Cup))]));
+        // The 7 cannot be given back for the 2
+        assert_eq!(state.apply(Action::ExchangeTrump(1)), Err(RuleError::CannotExchangeTrump));
+        assert_eq!(state.trump, card(2, Suit::Cup));
+        assert_eq!(state.deck[0], card(2, Suit::Cup));
+        assert!(state.hands[1].contains(&card(12, Suit::Cup)) && state.hands[1].contains(&card(7, Suit::Cup)));
+    }
+
+    #[test]
+    fn seven_of_trumps_is_only_exchanged_on_the_last_deal() {
+        let rules = Rules { trump_exchange: true, ..Default::default() };
+        let hand = [card(7, Suit::Cup)];
+        assert_eq!(trump_exchange_card(&rules, 2, &hand, &card(1, Suit::Cup), 4), None);
+        assert_eq!(trump_exchange_card(&rules, 2, &hand, &card(1, Suit::Cup), 2), Some(card(7, Suit::Cup)));
+        // Nor for a lower trump card
+        assert_eq!(trump_exchange_card(&rules, 2, &hand, &card(5, Suit::Cup), 2), None);
+        // The trump card has been dealt
+        assert_eq!(trump_exchange_card(&rules, 2, &hand, &card(1, Suit::Cup), 0), None);
+    }
+
+    // Game of 2 players with declarations and hands of 2 cards, the 4 of the trump suit as trump card.
+    // Seat 0 wins the first round with the ace of coins and draws the horse of cups, so that they
+    // lead the second round holding the king and horse of cups. Seat 1 holds the 6 of swords and the trump card
+    fn declaring_game(trump: Suit, winning_score: Option<u8>) -> GameState {
+        let deck = vec![
+            card(4, trump),
+            card(11, Suit::Cup),
+            card(6, Suit::Sword),
+            card(5, Suit::Coin),
+            card(12, Suit::Cup),
+            card(1, Suit::Coin)
+        ];
+        let rules = Rules { variant: Variant::TwoCards, declarations: true, winning_score, ..Default::default() };
+        let mut state = GameState::new(2, rules).unwrap();
+        state.start(deck, 0).unwrap();
+        state.apply(Action::Play(0, card(1, Suit::Coin))).unwrap();
+        state.apply(Action::Play(1, card(5, Suit::Coin))).unwrap();
+        state
+    }
+
+    #[test]
+    fn only_the_leader_declares_after_the_first_round() {
+        let rules = Rules { declarations: true, ..Default::default() };
+        let hand = [card(11, Suit::Cup), card(12, Suit::Cup), card(11, Suit::Sword)];
+        assert_eq!(declarable_suits(&rules, &hand, &[], 2, true), vec![Suit::Cup]);
+        assert!(declarable_suits(&rules, &hand, &[], 1, true).is_empty());
+        assert!(declarable_suits(&rules, &hand, &[], 2, false).is_empty());
+        assert!(declarable_suits(&Rules::default(), &hand, &[], 2, true).is_empty());
+
+        let mut state = declaring_game(Suit::Cup, None);
+        assert_eq!(state.declarable_suits(0), vec![Suit::Cup]);
+        assert_eq!(state.apply(Action::Declare(1, Suit::Cup)), Err(RuleError::NotYourTurn));
+    }
+
+    #[test]
+    fn each_suit_is_declared_once_and_trumps_score_40() {
+        let mut state = declaring_game(Suit::Cup, None);
+        assert_eq!(state.apply(Action::Declare(0, Suit::Cup)), Ok(vec![Event::Declared(0, Suit::Cup, 40, vec![51, 0])]));
+        assert_eq!(state.apply(Action::Declare(0, Suit::Cup)), Err(RuleError::CannotDeclare));
+        assert!(state.declarable_suits(0).is_empty());
+
+        let mut state = declaring_game(Suit::Sword, None);
+        assert_eq!(state.apply(Action::Declare(0, Suit::Cup)), Ok(vec![Event::Declared(0, Suit::Cup, 20, vec![31, 0])]));
+    }
+
+    #[test]
+    fn declared_points_count_towards_the_totals() {
+        let mut state = declaring_game(Suit::Cup, None);
+        state.apply(Action::Declare(0, Suit::Cup)).unwrap();
+        state.apply(Action::Play(0, card(12, Suit::Cup))).unwrap();
+        let events = state.apply(Action::Play(1, card(4, Suit::Cup))).unwrap();
+        assert!(matches!(&events[1], Event::RoundEnd(_, totals) if *totals == vec![55, 0]));
+
+        state.apply(Action::Play(0, card(11, Suit::Cup))).unwrap();
+        let events = state.apply(Action::Play(1, card(6, Suit::Sword))).unwrap();
+        assert_eq!(events.last(), Some(&Event::GameEnd(Outcome::Winners(vec![0]), vec![58, 0])));
+    }
+
+    #[test]
+    fn declaring_up_to_the_winning_score_ends_the_game() {
+        let mut state = declaring_game(Suit::Cup, Some(50));
+        assert_eq!(state.apply(Action::Declare(0, Suit::Cup)), Ok(vec![
+            Event::Declared(0, Suit::Cup, 40, vec![51, 0]),
+            Event::GameEnd(Outcome::Winners(vec![0]), vec![51, 0])
+        ]));
+        assert_eq!(state.phase, Phase::Finished);
+    }
+
+    #[test]
+    fn dealer_and_first_player_rotate_every_game() {
+        let mut state = MatchState::new(3, Rules::default(), 5, 0).unwrap();
+        let mut firsts = Vec::new();
+        for _ in 0..4 {
+            let events = state.deal(&[]).unwrap();
+            let Some(Event::Started(_, first)) = events.last() else {
+                panic!("The game did not start");
+            };
+            firsts.push(*first);
+            assert_eq!(*first, (state.dealer + 1) % 3);
+            // Skip to the end of the game
+            state.game.phase = Phase::Finished;
+        }
+        assert_eq!(firsts, vec![0, 1, 2, 0]);
+        assert_eq!(state.hand, 4);
+    }
+
+    #[test]
+    fn games_are_won_by_teams_until_the_match_is() {
+        for match_length in [1, 2] {
+            let mut state = MatchState::new(4, partners_game().rules, match_length, 0).unwrap();
+            // Play the partners' game instead of a shuffled deck
+            state.hand = 1;
+            state.game = partners_game();
+            let mut events = Vec::new();
+            for action in partners_plays() {
+                events = state.apply(action).unwrap();
+            }
+
+            // Both partners won, but their team only won one game
+            assert!(events.contains(&Event::MatchScore(vec![1, 0])));
+            let match_end = Event::MatchEnd(Outcome::Winners(vec![0, 2]), vec![1, 0]);
+            assert_eq!(events.contains(&match_end), match_length == 1);
+            assert_eq!(state.is_finished(), match_length == 1);
+            assert_eq!(state.needs_deal(), match_length == 2);
+        }
+    }
+
+    #[test]
+    fn forfeiting_before_the_first_deal_ends_the_match() {
+        let mut state = MatchState::new(2, Rules::default(), 3, 0).unwrap();
+        assert!(state.needs_deal());
+        assert_eq!(state.forfeit(1), Ok(vec![Event::MatchEnd(Outcome::Winners(vec![0]), vec![0, 0])]));
+        assert!(state.is_finished() && !state.needs_deal());
+        assert_eq!(state.deal(&[]).err(), Some(RuleError::Finished));
+        assert_eq!(state.forfeit(0).err(), Some(RuleError::Finished));
+    }
+
+    #[test]
+    fn same_seed_deals_the_same_decks() {
+        let seeds = vec!["a".to_string(), "b".to_string()];
+        let deal_match = |seed| {
+            let mut state = MatchState::new(2, Rules::default(), 3, seed).unwrap();
+            let mut games = vec![state.deal(&seeds).unwrap()];
+            // Skip to the end of the first game
+            state.game.phase = Phase::Finished;
+            games.push(state.deal(&seeds).unwrap());
+            games
+        };
+
+        let games = deal_match(7);
+        assert_eq!(games, deal_match(7));
+        // Pinned, so that a change of generator does not go unnoticed
+        let mut state = MatchState::new(2, Rules::default(), 3, 7).unwrap();
+        let commitment = state.seed_commitment();
+        state.deal(&seeds).unwrap();
+        assert_eq!(state.server_seed, "19454a27b752f905909507d6160ddc888e2df8b773098ef3f7bcd321a7caa748");
+        // The server seed was committed to before the players sent theirs
+        assert_eq!(fair::seed_hash(&state.server_seed), commitment);
+        assert_ne!(state.seed_commitment(), commitment);
+        assert_ne!(games[0], games[1]);
+        assert_ne!(games, deal_match(8));
+    }
+
+    // Game of 4 players in teams, hands of 2 cards and the whole deck dealt. Batons are trumps
+    fn partners_game() -> GameState {
+        let deck = vec![
+            card(4, Suit::Baton),
+            card(5, Suit::Coin),
+            card(11, Suit::Baton),
+            card(3, Suit::Coin),
+            card(7, Suit::Baton),
+            card(4, Suit::Coin),
+            card(6, Suit::Baton),
+            card(1, Suit::Coin)
+        ];
+        let rules = Rules { variant: Variant::TwoCards, teams: true, ..Default::default() };
+        let mut state = GameState::new(4, rules).unwrap();
+        state.start(deck, 0).unwrap();
+        state
+    }
+
+    // Every card of the partners' game. Seat 0 wins the first round with the ace,
+    // seat 2 the second one with the horse of trumps
+    fn partners_plays() -> Vec<Action> {
+        [
+            (0, card(1, Suit::Coin)), (1, card(4, Suit::Coin)), (2, card(3, Suit::Coin)), (3, card(5, Suit::Coin)),
+            (0, card(6, Suit::Baton)), (1, card(7, Suit::Baton)), (2, card(11, Suit::Baton)), (3, card(4, Suit::Baton))
+        ].into_iter().map(|(seat, card)| Action::Play(seat, card)).collect()
+    }
+
+    #[test]
+    fn partners_sitting_across_score_together() {
+        let mut state = partners_game();
+        assert_eq!(state.teams, vec![0, 1, 0, 1]);
+        assert_eq!((state.partner(0), state.partner(1)), (Some(2), Some(3)));
+
+        let mut events = Vec::new();
+        for action in partners_plays() {
+            events = state.apply(action).unwrap();
+        }
+
+        assert_eq!(state.scores, vec![21, 0, 3, 0]);
+        assert_eq!(events.last(), Some(&Event::GameEnd(Outcome::Winners(vec![0, 2]), vec![24, 0])));
+    }
+
+    #[test]
+    fn teams_are_only_for_4_players() {
+        let rules = Rules { teams: true, ..Default::default() };
+        assert_eq!(GameState::new(2, rules.clone()).err(), Some(RuleError::UnsupportedPlayers));
+        assert_eq!(GameState::new(3, rules).err(), Some(RuleError::UnsupportedPlayers));
+        // Without teams everyone plays for themselves
+        let state = GameState::new(4, Rules::default()).unwrap();
+        assert_eq!(state.teams, vec![0, 1, 2, 3]);
+        assert_eq!(state.partner(0), None);
+    }
+
+    #[test]
+    fn three_players_play_without_the_2_of_coins() {
+        assert_eq!(deck(2).len(), 40);
+        assert_eq!(deck(3).len(), 39);
+        assert!(!deck(3).contains(&card(2, Suit::Coin)));
+
+        let mut state = GameState::new(3, Rules::default()).unwrap();
+        state.start(deck(3), 0).unwrap();
+        // Every card is played, each player playing the first card of their hand
+        let mut plays = 0;
+        while state.phase == Phase::Playing {
+            let seat = state.turn;
+            state.apply(Action::Play(seat, state.hands[seat as usize][0].clone())).unwrap();
+            plays += 1;
+        }
+        assert_eq!(plays, 39);
+        assert!(state.deck.is_empty() && state.hands.iter().all(Vec::is_empty));
+        // The 2 of coins is worth no points
+        assert_eq!(state.team_scores().iter().map(|&total| total as u32).sum::<u32>(), 120);
+    }
+
+    #[test]
+    fn rejects_plays_out_of_turn_or_of_cards_not_in_hand() {
+        let (mut state, _) = short_game();
+        assert_eq!(state.apply(Action::Play(0, card(1, Suit::Sword))), Err(RuleError::NotYourTurn));
+        assert_eq!(state.apply(Action::Play(1, card(1, Suit::Sword))), Err(RuleError::CardNotInHand));
+        assert_eq!(state.apply(Action::Play(2, card(1, Suit::Sword))), Err(RuleError::UnknownSeat));
+        // Nothing changed
+        assert_eq!(state.hands[1].len(), 3);
+        assert!(state.played.is_empty());
+        assert_eq!(state.turn, 1);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{Card, Event, Suit};
+use crate::rules::{self, Action, GameState, Rules};
+
+
+/// What the player sitting at `seat` knows about the game: their own hand and the cards on the table.
+/// Built from the game state on the server, or kept up to date from the game events by a remote player.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlayerView {
+    pub seat: u8,
+    pub rules: Rules,
+    pub players: Vec<String>,
+    // Team of the player sitting at each seat
+    pub teams: Vec<u8>,
+    pub hand: Vec<Card>,
+    pub trump: Card,
+    // Cards played so far this round and the seat of the player that played each of them
+    pub trick: Vec<(u8, Card)>,
+    // Cards of the rounds already played this game
+    pub seen: Vec<Card>,
+    // Cards left to be dealt, the trump card included
+    pub deck: u8,
+    pub round: u8,
+    pub turn: u8,
+    // Points of each team
+    pub score: Vec<u8>,
+    // Suits declared this game
+    pub declared: Vec<Suit>,
+    // Trump cards already taken in exchange this game
+    pub exchanged: Vec<Card>,
+    pub playing: bool,
+    // Waiting for the card drawn at the end of the last round
+    pub drawing: bool
+}
+
+/// A way of playing Brisca. Asked for its next move whenever it is the player's turn,
+/// by the server and the tournament in process or by the runner over the network.
+pub trait Strategy {
+    /// Card of `view.hand` to play. Any card of the hand is a legal play.
+    fn choose(&mut self, view: &PlayerView) -> Card;
+
+    /// Whether to swap `card` for the trump card, when the rules allow it. Asked before choosing a card.
+    fn exchange_trump(&mut self, _view: &PlayerView, _card: &Card) -> bool {
+        false
+    }
+
+    /// Suit to declare among `suits`, if any. Asked before leading a round.
+    fn declare(&mut self, _view: &PlayerView, _suits: &[Suit]) -> Option<Suit> {
+        None
+    }
+}
+
+/// Next move of the strategy playing the view's seat. It must be that seat's turn.
+pub fn next_action(strategy: &mut dyn Strategy, view: &PlayerView) -> Action {
+    let suits = view.declarable_suits();
+    if let Some(suit) = strategy.declare(view, &suits).filter(|suit| suits.contains(suit)) {
+        return Action::Declare(view.seat, suit);
+    }
+    if let Some(card) = view.trump_exchange_card() {
+        if strategy.exchange_trump(view, &card) {
+            return Action::ExchangeTrump(view.seat);
+        }
+    }
+    Action::Play(view.seat, strategy.choose(view))
+}
+
+impl PlayerView {
+    pub fn new(state: &GameState, seat: u8) -> PlayerView {
+        let first = (state.turn + state.num_players - state.played.len() as u8) % state.num_players;
+        PlayerView {
+            seat,
+            rules: state.rules.clone(),
+            players: Vec::new(),
+            teams: state.teams.clone(),
+            hand: state.hands[seat as usize].clone(),
+            trump: state.trump.clone(),
+            trick: state.played
+                .iter()
+                .enumerate()
+                .map(|(i, card)| ((first + i as u8) % state.num_players, card.clone()))
+                .collect(),
+            seen: state.seen.clone(),
+            deck: state.deck.len() as u8,
+            round: state.round,
+            turn: state.turn,
+            score: state.team_scores(),
+            declared: state.declared.clone(),
+            exchanged: state.exchanged.clone(),
+            playing: state.phase == rules::Phase::Playing,
+            drawing: false
+        }
+    }
+
+    pub fn num_players(&self) -> u8 {
+        self.teams.len() as u8
+    }
+
+    /// Seat of the player on the same team, if playing in teams.
+    pub fn partner(&self) -> Option<u8> {
+        let team = self.teams.get(self.seat as usize)?;
+        (0..self.num_players()).find(|&other| other != self.seat && self.teams[other as usize] == *team)
+    }
+
+    /// Card of the hand that can be swapped for the trump card, if any. Each trump card can only be taken once.
+    pub fn trump_exchange_card(&self) -> Option<Card> {
+        if self.exchanged.contains(&self.trump) {
+            return None;
+        }
+        rules::trump_exchange_card(&self.rules, self.num_players(), &self.hand, &self.trump, self.deck as usize)
+    }
+
+    /// Suits the player can declare before leading the round.
+    pub fn declarable_suits(&self) -> Vec<Suit> {
+        rules::declarable_suits(&self.rules, &self.hand, &self.declared, self.round, self.trick.is_empty())
+    }
+
+    pub fn trick_cards(&self) -> Vec<Card> {
+        self.trick.iter().map(|(_, card)| card.clone()).collect()
+    }
+
+    /// Seat of the player winning the trick played so far.
+    pub fn trick_winner(&self) -> Option<u8> {
+        if self.trick.is_empty() {
+            return None;
+        }
+        Some(self.trick[rules::round_winner(&self.trick_cards(), &self.trump.suit)].0)
+    }
+
+    /// It is this player's turn and their hand is complete.
+    pub fn can_play(&self) -> bool {
+        // Nobody plays once every card of the round is on the table, until the round ends
+        let round_over = self.trick.len() >= self.num_players() as usize;
+        self.playing && !self.drawing && !round_over && self.turn == self.seat && !self.hand.is_empty()
+    }
+
+    /// Keep the view up to date with an event of the player's game stream.
+    pub fn update(&mut self, player_id: &str, event: &Event) {
+        match event {
+            Event::Connected(players) => {
+                self.players = players.clone();
+                self.seat = players.iter().position(|p| p == player_id).unwrap_or_default() as u8;
+            },
+            Event::NewCard(card) => {
+                self.hand.push(card.clone());
+                self.drawing = false;
+            },
+            Event::GameStart(trump, teams, first, _) => {
+                self.trump = trump.clone();
+                self.teams = teams.clone();
+                self.turn = *first;
+                self.round = 1;
+                self.trick.clear();
+                self.seen.clear();
+                self.declared.clear();
+                self.exchanged.clear();
+                // Hands are dealt before the game starts
+                self.deck = (rules::deck(self.num_players()).len() - self.hand.len() * self.num_players() as usize) as u8;
+                let num_teams = teams.iter().max().map_or(0, |team| team + 1);
+                self.score = vec![0; num_teams as usize];
+                self.playing = true;
+            },
+            Event::PlayedCard(seat, card) => {
+                if *seat == self.seat {
+                    self.hand.retain(|c| c != card);
+                }
+                self.trick.push((*seat, card.clone()));
+                self.turn = (seat + 1) % self.num_players();
+            },
+            Event::TrumpExchanged(seat, card) => {
+                if *seat == self.seat {
+                    // Take the previous trump card in exchange of ours
+                    self.hand.retain(|c| c != card);
+                    self.hand.push(self.trump.clone());
+                }
+                self.exchanged.push(std::mem::replace(&mut self.trump, card.clone()));
+            },
+            Event::Declared(_, suit, _, score) => {
+                self.declared.push(suit.clone());
+                self.score = score.clone();
+            },
+            Event::RoundEnd(trick, score) => {
+                self.seen.extend(trick.cards.iter().map(|(_, card)| card.clone()));
+                self.trick.clear();
+                self.turn = trick.winner;
+                self.round = trick.round + 1;
+                // Every player draws a card while the deck lasts
+                self.drawing = trick.deck < self.deck;
+                self.deck = trick.deck;
+                self.score = score.clone();
+            },
+            Event::GameEnd(_, score, _) => {
+                self.score = score.clone();
+                self.hand.clear();
+                self.trick.clear();
+                self.playing = false;
+            },
+            Event::MatchEnd(..) => {
+                self.playing = false;
+            },
+            Event::TurnStarted(seat, _) => {
+                self.turn = *seat;
+            },
+            Event::Snapshot(snapshot) => {
+                let num_players = snapshot.players.len() as u8;
+                let first = (snapshot.turn + num_players - snapshot.played.len() as u8) % num_players;
+                *self = PlayerView {
+                    seat: snapshot.seat,
+                    // The rules are not part of the game events
+                    rules: self.rules.clone(),
+                    players: snapshot.players.clone(),
+                    teams: snapshot.teams.clone(),
+                    hand: snapshot.cards.clone(),
+                    trump: snapshot.trump.clone(),
+                    trick: snapshot.played
+                        .iter()
+                        .enumerate()
+                        .map(|(i, card)| ((first + i as u8) % num_players, card.clone()))
+                        .collect(),
+                    seen: snapshot.seen.clone(),
+                    deck: snapshot.deck,
+                    round: snapshot.round,
+                    turn: snapshot.turn,
+                    score: snapshot.score.clone(),
+                    declared: snapshot.declared.clone(),
+                    exchanged: snapshot.exchanged.clone(),
+                    playing: snapshot.started && !snapshot.finished && !snapshot.cards.is_empty(),
+                    drawing: false
+                };
+            },
+            Event::SeedRequested(_) | Event::MatchScore(_) | Event::PlayerLeft(..) => {}
+        }
+    }
+}
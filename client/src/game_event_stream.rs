@@ -1,56 +1,108 @@
-use api::Event;
+use api::{ErrorResponse, Event, EventEnvelope};
 
-use bytes::Bytes;
-use eventsource_stream::Eventsource;
+use eventsource_stream::{EventStreamError, Eventsource};
 use iced::futures::stream::BoxStream;
 use iced::futures::{SinkExt, StreamExt};
 use iced::command::{channel, Command};
 use reqwest::{Client, Error};
+use std::time::Duration;
 
 
+// Delay before the first reconnection attempt, doubled on every failed attempt
+static MIN_BACKOFF: Duration = Duration::from_secs(1);
+static MAX_BACKOFF: Duration = Duration::from_secs(30);
+
 enum State<'a> {
     Starting,
-    Ready(BoxStream<'a, Result<Bytes, Error>>),
+    Ready(BoxStream<'a, Result<eventsource_stream::Event, EventStreamError<Error>>>),
+    Reconnecting(Duration),
     Finished
 }
 
-pub fn connect(url: String, player_id: String) -> Command<Event> {
+pub fn connect(url: String, token: String, player_id: String) -> Command<Event> {
     channel(100, |mut output| async move {
         let mut state = State::Starting;
+        let mut backoff = MIN_BACKOFF;
+        // Sequence number of the last event received, to resume from it when reconnecting
+        let mut last_seq = 0;
         loop {
             match &mut state {
                 State::Starting => {
                     // Get game stream events
-                    let stream = Client::new()
+                    let mut request = Client::new()
                         .get(&url)
-                        .header("authorization", &player_id) // TODO: Implement proper auth
-                        .send()
-                        .await
-                        .unwrap()
-                        .bytes_stream()
-                        .boxed();
-                    state = State::Ready(stream);
+                        .bearer_auth(&token);
+                    if last_seq > 0 {
+                        request = request.header("Last-Event-ID", last_seq);
+                    }
+                    let response = match request.send().await {
+                        Ok(response) => response,
+                        Err(error) => {
+                            println!("Could not connect to game: {:?}", error);
+                            state = State::Reconnecting(backoff);
+                            continue;
+                        }
+                    };
+                    if !response.status().is_success() {
+                        // The server refused to let us join the game
+                        match response.json::<ErrorResponse>().await {
+                            Ok(error) => println!("Could not join game: {}", error.message),
+                            Err(error) => println!("Could not join game: {:?}", error)
+                        }
+                        state = State::Finished;
+                        continue;
+                    }
+                    backoff = MIN_BACKOFF;
+                    state = State::Ready(response.bytes_stream().eventsource().boxed());
                 },
                 State::Ready(stream) => {
-                    if let Some(event) = stream.eventsource().next().await {
-                        match event {
-                            Ok(event) => {
-                                let game_event: Event = serde_json::from_str(&event.data).unwrap();
-
-                                if let Event::GameEnd(_) = game_event {
-                                    // This is the last event for the game.
-                                    state = State::Finished;
+                    match stream.next().await {
+                        Some(Ok(event)) => {
+                            let envelope: EventEnvelope = match serde_json::from_str(&event.data) {
+                                Ok(envelope) => envelope,
+                                Err(error) => {
+                                    println!("Could not read game event: {:?}", error);
+                                    continue;
                                 }
+                            };
+                            if envelope.seq <= last_seq {
+                                // Already received before reconnecting
+                                continue;
+                            }
+                            if envelope.seq > last_seq + 1 {
+                                println!("Missed game events {} to {}", last_seq + 1, envelope.seq - 1);
+                            }
+                            last_seq = envelope.seq;
+                            let game_event = envelope.event;
 
-                                let _ = output.send(game_event).await;
-                            },
-                            Err(error) => {
-                                // Error parsing the event
-                                println!("{:?}", error);
+                            match &game_event {
+                                // This is the last event for the game.
+                                Event::MatchEnd(..) => state = State::Finished,
+                                // We left the game. The server closes our stream
+                                Event::PlayerLeft(id, _) if *id == player_id => state = State::Finished,
+                                _ => {}
                             }
+
+                            let _ = output.send(game_event).await;
+                        },
+                        Some(Err(error)) => {
+                            // Error parsing the event
+                            println!("{:?}", error);
+                        },
+                        None => {
+                            // The connection dropped before the game finished.
+                            // The server replays the events we missed when we reconnect
+                            println!("Game event stream disconnected");
+                            state = State::Reconnecting(backoff);
                         }
                     }
                 },
+                State::Reconnecting(delay) => {
+                    println!("Reconnecting in {:?}", delay);
+                    tokio::time::sleep(*delay).await;
+                    backoff = (*delay * 2).min(MAX_BACKOFF);
+                    state = State::Starting;
+                },
                 State::Finished => {
                     println!("Game event stream has finished");
                     break;
@@ -58,4 +110,4 @@ pub fn connect(url: String, player_id: String) -> Command<Event> {
             }
         }
     })
-}
\ No newline at end of file
+}
@@ -1,23 +1,28 @@
 mod game_event_stream;
 use api;
 
-use api::{Card, Event, GameInfo, Suit};
+use api::{Card, Credentials, Departure, ErrorCode, ErrorResponse, Event, GameConfig, GameInfo, GameQuery, GameResult, GameSnapshot, GameStatus, LeavePolicy, StatusFilter, NewGame, Session, Suit};
+use api::bot::BotLevel;
+use api::fair;
+use api::rules::{self, Rules, Variant};
 use iced::executor;
-use iced::{Alignment, Application, Command, Element, Settings, Theme};
-use iced::widget::{Button, column, Column, row, Row, Text, image::{Image, Handle}};
+use iced::{time, Alignment, Application, Command, Element, Settings, Subscription, Theme};
+use iced::widget::{Button, Checkbox, column, Column, row, Row, Text, TextInput, image::{Image, Handle}};
 use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 static API_URL: &str = "http://127.0.0.1:3030";
 
 pub fn main() -> iced::Result {
     let mut args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    if args.len() != 3 {
         eprint!("{:?}", args);
-        panic!("Run command: client [player_id]")
+        panic!("Run command: client [player_id] [password]")
     }
+    let password = args.pop().unwrap();
     let player_id = args.pop().unwrap();
 
-    App::run(Settings::with_flags(player_id))
+    App::run(Settings::with_flags((player_id, password)))
 }
 
 #[derive(Clone, Debug, Default)]
@@ -32,19 +37,106 @@ enum State {
 #[derive(Clone, Debug, Default)]
 struct Game {
     id: String,
-    winner: String,
+    config: GameConfig,
+    // Invite code of a private game
+    invite: Option<String>,
+    // Result of the last game played
+    result: Option<GameResult>,
+    // Result of the match once finished
+    match_result: Option<GameResult>,
+    // Games won by each team in the match
+    wins: Vec<u8>,
+    hand: u8,
     turn: u8,
+    // When the current turn runs out, in milliseconds since the Unix epoch
+    deadline: Option<u64>,
     round: u8,
     trump: Card,
+    // Cards left to be dealt, the trump card included
+    deck: u8,
     players: Vec<String>,
+    // Team of the player sitting at each seat
+    teams: Vec<u8>,
     played: Vec<Card>,
+    // Points of each team
     score: Vec<u8>,
-    cards: Vec<Card>
+    cards: Vec<Card>,
+    // Suits already declared this game
+    declared: Vec<Suit>,
+    // Players that have left the game
+    notice: Option<String>,
+    // Whether the deck of the last game matched the commitment sent when it started
+    audit: Option<String>,
+    // Events of the current game, to check its deck once revealed
+    events: Vec<Event>,
+    error: Option<String>
+}
+
+impl Game {
+    // Value of each player, or of each team when playing in teams
+    fn team_lines(&self, values: &[u8]) -> Vec<String> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(team, score)| {
+                let members: Vec<&str> = self.players
+                    .iter()
+                    .zip(self.teams.iter())
+                    .filter(|(_, t)| **t as usize == team)
+                    .map(|(player, _)| player.as_str())
+                    .collect();
+                if members.len() > 1 {
+                    format!("Team {} ({}): {}", team + 1, members.join(", "), score)
+                } else {
+                    format!("{}: {}", members.join(", "), score)
+                }
+            })
+            .collect()
+    }
+
+    // Suits the player can declare, following the same rules as the server
+    fn declarable_suits(&self, player_id: &str) -> Vec<Suit> {
+        let leading = self.played.is_empty() && self.players.get(self.turn as usize).map(String::as_str) == Some(player_id);
+        rules::declarable_suits(&self.config.rules, &self.cards, &self.declared, self.round, leading)
+    }
+
+    // Seat and name of the player on the same team
+    fn partner(&self, player_id: &str) -> Option<(usize, &String)> {
+        let seat = self.players.iter().position(|p| p == player_id)?;
+        let team = self.teams.get(seat)?;
+        self.players
+            .iter()
+            .enumerate()
+            .find(|(other, _)| *other != seat && self.teams.get(*other) == Some(team))
+    }
 }
 
 #[derive(Default)]
 struct App {
     player_id: String,
+    // Session token sent with every request
+    token: String,
+    // Error shown on the main screen
+    error: Option<String>,
+    // Rules of the games created by this player
+    rules: Rules,
+    // Games to win the matches created by this player
+    match_length: u8,
+    // Create private games that can only be joined with an invite code
+    private: bool,
+    // What happens to the games created by this player when someone leaves
+    on_leave: LeavePolicy,
+    // Time limit of each turn in the games created by this player
+    turn_seconds: Option<u32>,
+    // Fill every other seat of the games created by this player with bots
+    fill_with_bots: bool,
+    bot_level: BotLevel,
+    // Invite code typed in to join a private game
+    invite: String,
+    // Our part of the shuffle of the current deck
+    seed: String,
+    // Filters of the list of games
+    lobby: GameQuery,
     state: State,
     games: Vec<GameInfo>,
     game: Game
@@ -53,18 +145,41 @@ struct App {
 #[derive(Debug, Clone)]
 enum Message {
     None,
+    Authenticated(Result<Session, Error>),
     Navigate(State),
     RefreshGameList,
+    ToggleShowAll(bool),
     GameList(Result<Vec<GameInfo>, Error>),
+    ToggleDeclarations(bool),
+    ToggleTwoCards(bool),
+    ToggleTrumpExchange(bool),
+    ToggleBestOfThree(bool),
+    TogglePrivate(bool),
+    ToggleBotOnLeave(bool),
+    ToggleTurnTimer(bool),
+    ToggleFillWithBots(bool),
+    ToggleStrongBots(bool),
+    // Redraw the countdown of the current turn
+    Tick,
     CreateGame(u8),
-    JoinGame(String),
+    InviteChanged(String),
+    JoinWithInvite,
+    JoinGame(GameInfo, Option<String>),
     GameEvent(Event),
-    PlayCard(usize)
+    PlayCard(usize),
+    PlayRefused(ErrorResponse),
+    GameState(Result<GameSnapshot, Error>),
+    ExchangeTrump,
+    Declare(Suit),
+    LeaveGame,
+    Forfeit,
+    Refused(ErrorResponse)
 }
 
 #[derive(Debug, Clone)]
 enum Error {
-    APIError
+    APIError,
+    Refused(ErrorResponse)
 }
 
 impl From<reqwest::Error> for Error {
@@ -75,18 +190,61 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl App {
+    // Replace our state of the game with the one from the server
+    fn resync(&mut self, snapshot: GameSnapshot) {
+        self.state = if snapshot.finished {
+            State::Finished
+        } else if snapshot.started {
+            State::Ongoing
+        } else {
+            State::Waiting
+        };
+        self.game.players = snapshot.players;
+        self.game.teams = snapshot.teams;
+        self.game.cards = snapshot.cards;
+        self.game.trump = snapshot.trump;
+        self.game.deck = snapshot.deck;
+        self.game.played = snapshot.played;
+        self.game.score = snapshot.score;
+        self.game.turn = snapshot.turn;
+        self.game.round = snapshot.round;
+        self.game.declared = snapshot.declared;
+        self.game.hand = snapshot.hand;
+        self.game.wins = snapshot.wins;
+        self.game.deadline = snapshot.deadline;
+    }
+
+    // Send a new seed of ours for the next deck
+    fn send_seed(&mut self) -> Command<Message> {
+        self.seed = format!("{:032x}", rand::random::<u128>());
+
+        Command::perform(
+            send_seed(
+                self.seed.clone(),
+                self.token.clone(),
+                self.game.id.clone()),
+                |res| match res {
+                    Ok(_) => Message::None,
+                    Err(Error::Refused(error)) => Message::Refused(error),
+                    Err(_) => Message::None // TODO: Handle this
+                })
+    }
+}
+
 impl Application for App {
     type Executor = executor::Default;
     type Message = Message;
     type Theme = Theme;
-    type Flags = String;
+    type Flags = (String, String);
 
     fn new(flags: Self::Flags) -> (App, Command<Self::Message>) {
-        let player_id = flags;
+        let (player_id, password) = flags;
         (App {
             player_id: player_id.clone(),
+            match_length: 1,
             ..Default::default()
-        }, Command::perform(get_games(), Message::GameList))
+        }, Command::perform(authenticate(player_id, password), Message::Authenticated))
     }
 
     fn title(&self) -> String {
@@ -99,16 +257,39 @@ impl Application for App {
             // Dummy message to do nothing.
             // Used mainly for Successful API response that require no state change
             Message::None => Command::none(),
+            Message::Authenticated(result) => {
+                match result {
+                    Ok(session) => {
+                        self.token = session.token;
+                        self.error = None;
+
+                        Command::perform(get_games(self.lobby.clone(), self.token.clone()), Message::GameList)
+                    }
+                    Err(error) => {
+                        self.error = Some(match error {
+                            Error::Refused(error) => error.message,
+                            Error::APIError => "Could not reach the server".to_string()
+                        });
+
+                        Command::none()
+                    }
+                }
+            },
             Message::Navigate(state) => {
                 self.state = state.clone();
 
                 match state {
-                    State::Main => Command::perform(get_games(), Message::GameList),
+                    State::Main => Command::perform(get_games(self.lobby.clone(), self.token.clone()), Message::GameList),
                     _ => Command::none()
                 }
             },
             Message::RefreshGameList => {
-                Command::perform(get_games(), Message::GameList)
+                Command::perform(get_games(self.lobby.clone(), self.token.clone()), Message::GameList)
+            },
+            Message::ToggleShowAll(show_all) => {
+                self.lobby.status = if show_all { StatusFilter::All } else { StatusFilter::Waiting };
+
+                Command::perform(get_games(self.lobby.clone(), self.token.clone()), Message::GameList)
             },
             Message::GameList(result) => {
                 match result {
@@ -122,44 +303,193 @@ impl Application for App {
 
                 Command::none()
             },
+            Message::ToggleTwoCards(two_cards) => {
+                self.rules.variant = if two_cards { Variant::TwoCards } else { Variant::Classic };
+
+                Command::none()
+            },
+            Message::ToggleTrumpExchange(trump_exchange) => {
+                self.rules.trump_exchange = trump_exchange;
+
+                Command::none()
+            },
+            Message::ToggleBestOfThree(best_of_three) => {
+                self.match_length = if best_of_three { 2 } else { 1 };
+
+                Command::none()
+            },
+            Message::ToggleDeclarations(declarations) => {
+                self.rules.declarations = declarations;
+
+                Command::none()
+            },
+            Message::TogglePrivate(private) => {
+                self.private = private;
+
+                Command::none()
+            },
+            Message::ToggleTurnTimer(turn_timer) => {
+                self.turn_seconds = turn_timer.then_some(30);
+
+                Command::none()
+            },
+            Message::ToggleFillWithBots(fill_with_bots) => {
+                self.fill_with_bots = fill_with_bots;
+
+                Command::none()
+            },
+            Message::ToggleStrongBots(strong) => {
+                self.bot_level = if strong { BotLevel::Strong } else { BotLevel::Simple };
+
+                Command::none()
+            },
+            Message::Tick => Command::none(),
+            Message::ToggleBotOnLeave(bot) => {
+                self.on_leave = if bot { LeavePolicy::Bot } else { LeavePolicy::OpponentsWin };
+
+                Command::none()
+            },
             Message::CreateGame(num_players) => {
-                Command::perform(create_game(num_players), |res| match res {
-                    Ok(game_id) => Message::JoinGame(game_id),
+                let config = GameConfig {
+                    num_players,
+                    rules: Rules {
+                        // 4 player games are played in teams of two, partners sitting across
+                        teams: num_players == 4,
+                        ..self.rules.clone()
+                    },
+                    match_length: self.match_length,
+                    private: self.private,
+                    on_leave: self.on_leave.clone(),
+                    turn_seconds: self.turn_seconds,
+                    bots: if self.fill_with_bots { num_players - 1 } else { 0 },
+                    bot_level: self.bot_level.clone(),
+                    ..Default::default()
+                };
+                Command::perform(create_game(config.clone(), self.token.clone()), move |res| match res {
+                    Ok(NewGame { id, invite }) => Message::JoinGame(GameInfo { id, num_players, config, ..Default::default() }, invite),
+                    Err(_) => Message::None // TODO: Handle this
+                })
+            },
+            Message::InviteChanged(invite) => {
+                self.invite = invite;
+
+                Command::none()
+            },
+            Message::JoinWithInvite => {
+                let invite = self.invite.trim().to_string();
+                // Invite codes start with the id of the game
+                let game_id = invite.split('-').next().unwrap_or_default().to_string();
+
+                Command::perform(get_game_info(game_id, invite.clone(), self.token.clone()), move |res| match res {
+                    Ok(info) => Message::JoinGame(info, Some(invite)),
+                    Err(Error::Refused(error)) => Message::Refused(error),
                     Err(_) => Message::None // TODO: Handle this
                 })
             },
-            Message::JoinGame(game_id) => {
+            Message::JoinGame(GameInfo { id: game_id, config, .. }, invite) => {
                 self.state = State::Waiting;
-                self.game = Game { id: game_id.clone(), ..Default::default() };
+                self.error = None;
+                self.game = Game { id: game_id.clone(), config, invite: invite.clone(), ..Default::default() };
 
-                game_event_stream::connect(format!("{API_URL}/game/{game_id}"),
-                    self.player_id.clone())
+                let url = match invite {
+                    Some(invite) => format!("{API_URL}/game/{game_id}?invite={invite}"),
+                    None => format!("{API_URL}/game/{game_id}")
+                };
+                game_event_stream::connect(url, self.token.clone(), self.player_id.clone())
                     .map(Message::GameEvent)
             },
             Message::PlayCard(card_idx) => {
-                // Remove local copy
-                let card = self.game.cards.remove(card_idx);
+                self.game.error = None;
+                // The card leaves the hand once the server sends it back as played
+                let card = self.game.cards[card_idx].clone();
 
-                // Set as played card
-                self.game.played.push(card.clone());
+                Command::perform(
+                    play_card(
+                        card,
+                        self.token.clone(),
+                        self.game.id.clone()),
+                        |res| match res {
+                            Ok(_) => Message::None,
+                            Err(Error::Refused(error)) => Message::PlayRefused(error),
+                            Err(_) => Message::None // TODO: Handle this
+                        })
+            },
+            Message::PlayRefused(error) => {
+                self.game.error = Some(error.message);
 
-                // Update turn counter if this round has not finished yet
-                if self.game.played.len() < self.game.players.len() {
-                    self.game.turn = (self.game.turn + 1) % self.game.players.len() as u8;
+                // Our view of the game may be out of date. Get the state from the server
+                Command::perform(get_game_state(self.game.id.clone(), self.token.clone()), Message::GameState)
+            },
+            Message::GameState(result) => {
+                match result {
+                    Ok(snapshot) => self.resync(snapshot),
+                    Err(_) => println!("Error getting the state of the game.")
                 }
 
+                Command::none()
+            },
+            Message::ExchangeTrump => {
+                self.game.error = None;
+
                 Command::perform(
-                    play_card(
-                        card.clone(),
-                        self.player_id.clone(),
+                    exchange_trump(
+                        self.token.clone(),
                         self.game.id.clone()),
                         |res| match res {
                             Ok(_) => Message::None,
+                            Err(Error::Refused(error)) => Message::Refused(error),
                             Err(_) => Message::None // TODO: Handle this
                         })
+            },
+            Message::Declare(suit) => {
+                self.game.error = None;
+
+                Command::perform(
+                    declare(
+                        suit,
+                        self.token.clone(),
+                        self.game.id.clone()),
+                        |res| match res {
+                            Ok(_) => Message::None,
+                            Err(Error::Refused(error)) => Message::Refused(error),
+                            Err(_) => Message::None // TODO: Handle this
+                        })
+            },
+            Message::LeaveGame => {
+                Command::perform(
+                    leave_game(
+                        self.token.clone(),
+                        self.game.id.clone()),
+                        |res| match res {
+                            Ok(_) => Message::None,
+                            Err(Error::Refused(error)) => Message::Refused(error),
+                            Err(_) => Message::None // TODO: Handle this
+                        })
+            },
+            Message::Forfeit => {
+                self.game.error = None;
+
+                Command::perform(
+                    forfeit(
+                        self.token.clone(),
+                        self.game.id.clone()),
+                        |res| match res {
+                            Ok(_) => Message::None,
+                            Err(Error::Refused(error)) => Message::Refused(error),
+                            Err(_) => Message::None // TODO: Handle this
+                        })
+            },
+            Message::Refused(error) => {
+                match self.state {
+                    State::Main => self.error = Some(error.message),
+                    _ => self.game.error = Some(error.message)
+                }
+
+                Command::none()
             }
             Message::GameEvent(game_event) => {
                 println!("Received GameEvent: {:?}", game_event);
+                self.game.events.push(game_event.clone());
                 match game_event {
                     Event::Connected(players) => {
                         self.game.players = players;
@@ -171,46 +501,132 @@ impl Application for App {
 
                         Command::none()
                     },
-                    Event::GameStart(card) => {
+                    Event::SeedRequested(_) => self.send_seed(),
+                    Event::GameStart(card, teams, first, _) => {
                         self.state = State::Ongoing;
                         // Save trump card of this game
                         self.game.trump = card;
-                        // Set initial round
+                        // Set initial round and who plays first
                         self.game.round = 1;
+                        self.game.turn = first;
+                        self.game.hand += 1;
 
-                        // Initialize players score
-                        for _ in 0..self.game.players.len() {
-                            self.game.score.push(0);
-                        }
+                        // Hands are dealt before the game starts
+                        let num_players = teams.len();
+                        self.game.deck = (rules::deck(num_players as u8).len() - self.game.config.rules.hand_size() * num_players) as u8;
+
+                        // Initialize teams score
+                        let num_teams = teams.iter().max().map_or(0, |team| team + 1);
+                        self.game.score = vec![0; num_teams as usize];
+                        self.game.teams = teams;
 
                         Command::none()
                     },
-                    Event::PlayedCard(card) => {
+                    Event::PlayedCard(seat, card) => {
+                        // Our own card, played by us or by the server when our turn ran out
+                        if self.game.players[seat as usize] == self.player_id {
+                            self.game.cards.retain(|c| *c != card);
+                        }
                         self.game.played.push(card);
 
-                        // Update turn counter if this round has not finished yet
+                        // Next player's turn if this round has not finished yet
                         if self.game.played.len() < self.game.players.len() {
-                            self.game.turn= (self.game.turn + 1) % self.game.players.len() as u8;
+                            self.game.turn = (seat + 1) % self.game.players.len() as u8;
                         }
 
                         Command::none()
                     },
-                    Event::RoundEnd(winner, round_score) => {
+                    Event::TrumpExchanged(seat, card) => {
+                        if self.game.players[seat as usize] == self.player_id {
+                            // Take the previous trump card in exchange of ours
+                            self.game.cards.retain(|c| *c != card);
+                            self.game.cards.push(self.game.trump.clone());
+                        }
+                        self.game.trump = card;
+
+                        Command::none()
+                    },
+                    Event::Declared(_, suit, _, score) => {
+                        self.game.declared.push(suit);
+                        // Update score with the bonus points
+                        self.game.score = score;
+
+                        Command::none()
+                    },
+                    Event::RoundEnd(trick, score) => {
                         // Set next turn based on winner
-                        self.game.turn = winner;
+                        self.game.turn = trick.winner;
                         // Update score
-                        self.game.score[winner as usize] += round_score;
+                        self.game.score = score;
                         // Update round counter
-                        self.game.round += 1;
+                        self.game.round = trick.round + 1;
+                        self.game.deck = trick.deck;
                         // Clear played cards
                         self.game.played = Vec::with_capacity(self.game.players.len());
 
                         Command::none()
                     },
-                    Event::GameEnd(winner) => {
+                    Event::GameEnd(result, score, reveal) => {
+                        self.game.result = Some(result);
+                        // Check the deck we played against the commitment
+                        let seat = self.game.players.iter().position(|p| *p == self.player_id).unwrap_or_default() as u8;
+                        self.game.audit = Some(match fair::verify(seat, &self.seed, &self.game.events, &reveal) {
+                            Ok(()) => "The deck matched its commitment".to_string(),
+                            Err(error) => format!("Could not verify the deck: {}", error)
+                        });
+                        self.game.events.clear();
+                        // Final scores from the server
+                        self.game.score = score;
+                        // Clear the table for the next game of the match
+                        self.game.deadline = None;
+                        self.game.cards.clear();
+                        self.game.played.clear();
+                        self.game.declared.clear();
+
+                        Command::none()
+                    },
+                    Event::MatchScore(wins) => {
+                        self.game.wins = wins;
+
+                        Command::none()
+                    },
+                    Event::MatchEnd(result, wins) => {
                         self.state = State::Finished;
-                        self.game.winner = winner;
+                        self.game.match_result = Some(result);
+                        self.game.wins = wins;
+
+                        Command::none()
+                    },
+                    Event::PlayerLeft(player, departure) => {
+                        if player == self.player_id {
+                            // Back to the list of games
+                            self.state = State::Main;
+                            return Command::perform(get_games(self.lobby.clone(), self.token.clone()), Message::GameList);
+                        }
+                        self.game.notice = Some(match departure {
+                            Departure::Left => format!("{} left the game", player),
+                            Departure::Forfeited => format!("{} forfeited the game", player),
+                            Departure::Disconnected => format!("{} lost their connection", player),
+                            Departure::TimedOut => format!("{} ran out of time", player)
+                        });
+
+                        Command::none()
+                    },
+                    Event::TurnStarted(seat, deadline) => {
+                        self.game.turn = seat;
+                        self.game.deadline = Some(deadline);
+
+                        Command::none()
+                    },
+                    Event::Snapshot(snapshot) => {
+                        // Reconnected to the game. The events we missed are lost
+                        self.game.events.clear();
+                        let seed_requested = snapshot.seed_request.is_some();
+                        self.resync(snapshot);
 
+                        if seed_requested {
+                            return self.send_seed();
+                        }
                         Command::none()
                     }
                 }
@@ -218,40 +634,93 @@ impl Application for App {
         }
     }
 
+    fn subscription(&self) -> Subscription<Self::Message> {
+        match (&self.state, self.game.deadline) {
+            (State::Ongoing, Some(_)) => time::every(Duration::from_secs(1)).map(|_| Message::Tick),
+            _ => Subscription::none()
+        }
+    }
+
     fn view(&self) -> Element<Self::Message> {
         match self.state {
             State::Main => {
                 let games = Column::with_children(self.games
                     .iter()
-                    .map(| GameInfo {id, num_players} | {
-                        row![
-                            Text::new(format!("game_id: {} - num_players: {}", id, num_players)),
-                            Button::new("Join").on_press(Message::JoinGame(id.clone()))
+                    .map(|info| {
+                        let mut row = row![
+                            Text::new(format!("game_id: {} - {}/{} players: {} - {} - {:?} - {}",
+                                info.id,
+                                info.players.len(),
+                                info.num_players,
+                                info.players.join(", "),
+                                describe_rules(&info.config.rules),
+                                info.status,
+                                describe_age(info.created)))
                         ]
                         .spacing(20)
-                        .align_items(Alignment::Center)
+                        .align_items(Alignment::Center);
+                        if info.status == GameStatus::Waiting {
+                            row = row.push(Button::new("Join").on_press(Message::JoinGame(info.clone(), None)));
+                        } else if info.status == GameStatus::InProgress && info.players.contains(&self.player_id) && !info.left.contains(&self.player_id) {
+                            row = row.push(Button::new("Rejoin").on_press(Message::JoinGame(info.clone(), None)));
+                        }
+                        row
                     })
                     .map(Element::from)
                 );
                 column![
+                    Text::new(self.error.clone().unwrap_or_default()),
                     Text::new("Create game"),
+                    Checkbox::new("Brisca a 2 cartas", self.rules.variant == Variant::TwoCards).on_toggle(Message::ToggleTwoCards),
+                    Checkbox::new("Trump exchange", self.rules.trump_exchange).on_toggle(Message::ToggleTrumpExchange),
+                    Checkbox::new("Declarations", self.rules.declarations).on_toggle(Message::ToggleDeclarations),
+                    Checkbox::new("Best of 3", self.match_length == 2).on_toggle(Message::ToggleBestOfThree),
+                    Checkbox::new("Private", self.private).on_toggle(Message::TogglePrivate),
+                    Checkbox::new("30 second turns", self.turn_seconds.is_some()).on_toggle(Message::ToggleTurnTimer),
+                    Checkbox::new("Play against bots", self.fill_with_bots).on_toggle(Message::ToggleFillWithBots),
+                    Checkbox::new("Strong bots", self.bot_level == BotLevel::Strong).on_toggle(Message::ToggleStrongBots),
+                    Checkbox::new("Bot replaces players who leave", self.on_leave == LeavePolicy::Bot).on_toggle(Message::ToggleBotOnLeave),
                     Button::new("2 player").on_press(Message::CreateGame(2)),
+                    Button::new("3 player").on_press(Message::CreateGame(3)),
                     Button::new("4 player").on_press(Message::CreateGame(4)),
                     Text::new("Available games"),
+                    Checkbox::new("Show all games", self.lobby.status == StatusFilter::All).on_toggle(Message::ToggleShowAll),
                     games,
-                    Button::new("Refresh").on_press(Message::RefreshGameList)
+                    Button::new("Refresh").on_press(Message::RefreshGameList),
+                    row![
+                        TextInput::new("Invite code", &self.invite).on_input(Message::InviteChanged),
+                        Button::new("Join private game").on_press(Message::JoinWithInvite)
+                    ]
+                    .spacing(20)
                 ]
                 .spacing(10)
                 .into()
             },
             State::Ongoing => {
                 let round = Element::from(Text::new(format!("Round: {}", self.game.round)));
-                let scores = Column::with_children(self.game.players
-                    .iter()
-                    .zip(self.game.score.iter())
-                    .map(|(player, score)| Text::new(format!("{}: {}", player, score)))
+                let countdown = Element::from(Text::new(self.game.deadline
+                    .map(|deadline| {
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                        format!("Time left: {}s", deadline.saturating_sub(now) / 1000)
+                    })
+                    .unwrap_or_default()));
+                let scores = Column::with_children(self.game.team_lines(&self.game.score)
+                    .into_iter()
+                    .map(Text::new)
                     .map(Element::from)
                 );
+                let mut wins = Column::new();
+                if self.game.config.match_length > 1 {
+                    wins = wins
+                        .push(Text::new(format!("Game {} - first to win {} games", self.game.hand, self.game.config.match_length)))
+                        .push(Text::new(format!("Last game: {}", describe_result(&self.game.result))));
+                    for line in self.game.team_lines(&self.game.wins) {
+                        wins = wins.push(Text::new(line));
+                    }
+                }
+                let partner = Element::from(Text::new(self.game.partner(&self.player_id)
+                    .map(|(seat, partner)| format!("Partner: {} (seat {})", partner, seat + 1))
+                    .unwrap_or_default()));
 
                 let hand;
                 if self.game.players[self.game.turn as usize] == self.player_id {
@@ -272,7 +741,19 @@ impl Application for App {
                         .map(Element::from)
                     );
                 }
-                let trump = Element::from(Image::<Handle>::new(get_image_path(&self.game.trump)));
+                let mut trump = column![Image::<Handle>::new(get_image_path(&self.game.trump))];
+                let num_players = self.game.players.len() as u8;
+                let can_exchange = rules::trump_exchange_card(&self.game.config.rules, num_players, &self.game.cards, &self.game.trump, self.game.deck as usize).is_some();
+                if can_exchange && self.game.players[self.game.turn as usize] == self.player_id {
+                    trump = trump.push(Button::new("Exchange trump").on_press(Message::ExchangeTrump));
+                }
+
+                // Let the round winner declare the king and horse of a suit before leading
+                let declarations = Row::with_children(self.game.declarable_suits(&self.player_id)
+                    .into_iter()
+                    .map(|suit| Button::new(Text::new(format!("Declare {:?}", suit))).on_press(Message::Declare(suit)))
+                    .map(Element::from)
+                );
 
                 // Show played cards
                 let played = Row::with_children(self.game.played
@@ -280,30 +761,59 @@ impl Application for App {
                     .map(|c| Image::<Handle>::new(get_image_path(c)))
                     .map(Element::from));
 
+                let notice = Element::from(Text::new(self.game.notice.clone().unwrap_or_default()));
+                let error = Element::from(Text::new(self.game.error.clone().unwrap_or_default()));
+
                 Column::new()
+                    .push(wins)
                     .push(round)
+                    .push(countdown)
                     .push(scores)
+                    .push(partner)
                     .push(trump)
+                    .push(declarations)
                     .push(hand)
                     .push(played)
+                    .push(notice)
+                    .push(Text::new(self.game.audit.clone().unwrap_or_default()))
+                    .push(error)
+                    .push(Button::new("Forfeit").on_press(Message::Forfeit))
                     .into()
             }
             State::Finished => {
-                let scores = Column::with_children(self.game.players
-                    .iter()
-                    .zip(self.game.score.iter())
-                    .map(|(player, score)| Text::new(format!("{}: {}", player, score)))
+                let scores = Column::with_children(self.game.team_lines(&self.game.score)
+                    .into_iter()
+                    .map(Text::new)
                     .map(Element::from)
                 );
+                let mut wins = Column::new();
+                if self.game.config.match_length > 1 {
+                    wins = wins.push(Text::new("Games won"));
+                    for line in self.game.team_lines(&self.game.wins) {
+                        wins = wins.push(Text::new(line));
+                    }
+                }
 
                 Column::new()
                     .push(scores)
-                    .push(Element::from(Text::new(format!("Winner: {}", self.game.winner))))
+                    .push(wins)
+                    .push(Element::from(Text::new(describe_result(&self.game.match_result))))
+                    .push(Text::new(self.game.audit.clone().unwrap_or_default()))
                     .push(Button::new("Return to Main").on_press(Message::Navigate(State::Main)))
                     .into()
             }
             State::Waiting => {
-                Text::new("Waiting for players to connect ...").into()
+                let invite = match &self.game.invite {
+                    Some(invite) => format!("Invite code: {}", invite),
+                    None => String::new()
+                };
+                column![
+                    Text::new("Waiting for players to connect ..."),
+                    Text::new(invite),
+                    Text::new(self.game.notice.clone().unwrap_or_default()),
+                    Button::new("Leave").on_press(Message::LeaveGame)
+                ]
+                .into()
             }
         }
     }
@@ -320,23 +830,154 @@ fn get_image_path(Card {number, suit}: &Card) -> String {
         })
 }
 
+fn describe_result(result: &Option<GameResult>) -> String {
+    match result {
+        Some(GameResult::Winners(winners)) if winners.len() == 1 => format!("Winner: {}", winners[0]),
+        Some(GameResult::Winners(winners)) => format!("Tied winners: {}", winners.join(", ")),
+        Some(GameResult::Draw) => "Draw".to_string(),
+        Some(GameResult::Cancelled) => "Cancelled".to_string(),
+        Some(GameResult::Abandoned) => "Abandoned".to_string(),
+        None => String::new()
+    }
+}
+
+fn describe_age(created: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    format!("created {} min ago", now.saturating_sub(created) / 60)
+}
+
+fn describe_rules(rules: &Rules) -> String {
+    let mut names = vec![match rules.variant {
+        Variant::Classic => "Classic",
+        Variant::TwoCards => "Brisca a 2 cartas"
+    }.to_string()];
+    if rules.teams {
+        names.push("Teams".to_string());
+    }
+    if rules.trump_exchange {
+        names.push("Trump exchange".to_string());
+    }
+    if rules.declarations {
+        names.push("Declarations".to_string());
+    }
+    if let Some(winning_score) = rules.winning_score {
+        names.push(format!("First to {}", winning_score));
+    }
+    names.join(", ")
+}
+
 // API requests
 
-async fn play_card(card: Card, player_id: String, game_id: String) -> Result<(), Error> {
+// Log in, registering the player the first time
+async fn authenticate(player_id: String, password: String) -> Result<Session, Error> {
+    let credentials = Credentials { player_id, password };
+    let client = reqwest::Client::new();
+
+    let response = client.post(format!("{API_URL}/login")).json(&credentials).send().await?;
+    if response.status().is_success() {
+        return Ok(response.json().await?);
+    }
+    let login_error: ErrorResponse = response.json().await?;
+
+    let response = client.post(format!("{API_URL}/register")).json(&credentials).send().await?;
+    if response.status().is_success() {
+        return Ok(response.json().await?);
+    }
+    let register_error: ErrorResponse = response.json().await?;
+    if register_error.code == ErrorCode::PlayerExists {
+        // Registered already, so the password was wrong
+        return Err(Error::Refused(login_error));
+    }
+    Err(Error::Refused(register_error))
+}
+
+async fn play_card(card: Card, token: String, game_id: String) -> Result<(), Error> {
     let url = format!("{API_URL}/game/{game_id}");
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .put(url)
-        .header("authorization", player_id) // TODO: Implement proper auth
+        .bearer_auth(token)
         .json(&card)
         .send()
         .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(())
+}
+
+async fn send_seed(seed: String, token: String, game_id: String) -> Result<(), Error> {
+    let url = format!("{API_URL}/game/{game_id}/seed");
+    let response = reqwest::Client::new()
+        .put(url)
+        .bearer_auth(token)
+        .json(&seed)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(())
+}
+
+async fn exchange_trump(token: String, game_id: String) -> Result<(), Error> {
+    let url = format!("{API_URL}/game/{game_id}/trump");
+    let response = reqwest::Client::new()
+        .put(url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(())
+}
+
+async fn declare(suit: Suit, token: String, game_id: String) -> Result<(), Error> {
+    let url = format!("{API_URL}/game/{game_id}/declare");
+    let response = reqwest::Client::new()
+        .put(url)
+        .bearer_auth(token)
+        .json(&suit)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(())
+}
+
+async fn leave_game(token: String, game_id: String) -> Result<(), Error> {
+    let url = format!("{API_URL}/game/{game_id}");
+    let response = reqwest::Client::new()
+        .delete(url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(())
+}
+
+async fn forfeit(token: String, game_id: String) -> Result<(), Error> {
+    let url = format!("{API_URL}/game/{game_id}/forfeit");
+    let response = reqwest::Client::new()
+        .put(url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
     Ok(())
 }
 
-async fn get_games() -> Result<Vec<GameInfo>, Error> {
+async fn get_games(query: GameQuery, token: String) -> Result<Vec<GameInfo>, Error> {
     let url = format!("{API_URL}/game");
     let games = reqwest::Client::new()
         .get(url)
+        .bearer_auth(token)
+        .query(&query)
         .send()
         .await?
         .json()
@@ -344,13 +985,42 @@ async fn get_games() -> Result<Vec<GameInfo>, Error> {
     Ok(games)
 }
 
-async fn create_game(num_players: u8) -> Result<String, Error> {
-    let url = format!("{API_URL}/game/{num_players}");
-    let game_id = reqwest::Client::new()
+async fn create_game(config: GameConfig, token: String) -> Result<NewGame, Error> {
+    let url = format!("{API_URL}/game");
+    let game = reqwest::Client::new()
         .post(url)
+        .bearer_auth(token)
+        .json(&config)
         .send()
         .await?
-        .text()
+        .json()
         .await?;
-    Ok(game_id)
+    Ok(game)
+}
+
+async fn get_game_info(game_id: String, invite: String, token: String) -> Result<GameInfo, Error> {
+    let url = format!("{API_URL}/game/{game_id}/info");
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(token)
+        .query(&[("invite", invite)])
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(response.json().await?)
+}
+
+async fn get_game_state(game_id: String, token: String) -> Result<GameSnapshot, Error> {
+    let url = format!("{API_URL}/game/{game_id}/state");
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Refused(response.json().await?));
+    }
+    Ok(response.json().await?)
 }
\ No newline at end of file
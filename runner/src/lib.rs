@@ -0,0 +1,203 @@
+//! Plays a [`Strategy`] on the server as a headless player, over the same HTTP and
+//! Server-Sent Events protocol as the graphical client.
+
+use api::fair;
+use api::rules::Action;
+use api::strategy::{self, PlayerView, Strategy};
+use api::{Credentials, ErrorCode, ErrorResponse, Event, EventEnvelope, GameInfo, Session};
+use eventsource_stream::Eventsource;
+use futures_util::StreamExt;
+use reqwest::Client;
+use std::fmt;
+
+
+#[derive(Debug)]
+pub enum Error {
+    // The server could not be reached
+    Http(reqwest::Error),
+    // The server refused the request
+    Refused(ErrorResponse),
+    // The game event stream could not be read
+    Stream(String)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(error) => write!(f, "Could not reach the server: {}", error),
+            Error::Refused(error) => write!(f, "{}", error.message),
+            Error::Stream(error) => write!(f, "Could not read the game events: {}", error)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Error {
+        Error::Http(error)
+    }
+}
+
+/// A logged in player.
+pub struct Runner {
+    client: Client,
+    server: String,
+    player_id: String,
+    token: String
+}
+
+impl Runner {
+    /// Log in to the server at `server`, registering the player the first time.
+    pub async fn login(server: &str, player_id: &str, password: &str) -> Result<Runner, Error> {
+        let client = Client::new();
+        let credentials = Credentials { player_id: player_id.to_string(), password: password.to_string() };
+
+        let response = client.post(format!("{server}/login")).json(&credentials).send().await?;
+        let session: Session = if response.status().is_success() {
+            response.json().await?
+        } else {
+            let login_error: ErrorResponse = response.json().await?;
+            let response = client.post(format!("{server}/register")).json(&credentials).send().await?;
+            if !response.status().is_success() {
+                let register_error: ErrorResponse = response.json().await?;
+                if register_error.code == ErrorCode::PlayerExists {
+                    // Registered already, so the password was wrong
+                    return Err(Error::Refused(login_error));
+                }
+                return Err(Error::Refused(register_error));
+            }
+            response.json().await?
+        };
+
+        Ok(Runner { client, server: server.to_string(), player_id: session.player_id, token: session.token })
+    }
+
+    /// Join the game and play every turn with the strategy until the match ends.
+    pub async fn play(&self, game_id: &str, invite: Option<&str>, strategy: &mut impl Strategy) -> Result<(), Error> {
+        // The rules are not sent with the game events
+        let info = self.game_info(game_id, invite).await?;
+
+        let mut request = self.client
+            .get(format!("{}/game/{game_id}", self.server))
+            .bearer_auth(&self.token);
+        if let Some(invite) = invite {
+            request = request.query(&[("invite", invite)]);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(Error::Refused(response.json().await?));
+        }
+        println!("{} joined game {}", self.player_id, game_id);
+
+        let mut stream = response.bytes_stream().eventsource();
+        let mut view = PlayerView { rules: info.config.rules, ..PlayerView::default() };
+        // Table when we last moved, to move only once until the server confirms it
+        let mut last_move = None;
+        // Events of the current game, to check its deck once revealed
+        let mut events = Vec::new();
+        // Our part of the shuffle of the current deck
+        let mut seed = String::new();
+        while let Some(event) = stream.next().await {
+            let event = event.map_err(|error| Error::Stream(error.to_string()))?;
+            let envelope: EventEnvelope = serde_json::from_str(&event.data).map_err(|error| Error::Stream(error.to_string()))?;
+            view.update(&self.player_id, &envelope.event);
+            events.push(envelope.event.clone());
+
+            let seed_requested = match &envelope.event {
+                Event::SeedRequested(_) => true,
+                Event::Snapshot(snapshot) => snapshot.seed_request.is_some(),
+                _ => false
+            };
+            if seed_requested {
+                // A new seed of ours for every deck
+                seed = format!("{:032x}", rand::random::<u128>());
+                if let Err(error) = self.send_seed(game_id, &seed).await {
+                    eprintln!("Could not send our seed: {}", error);
+                }
+            }
+
+            match envelope.event {
+                Event::GameStart(..) => last_move = None,
+                Event::GameEnd(result, score, reveal) => {
+                    println!("Game over: {:?} {:?}", result, score);
+                    match fair::verify(view.seat, &seed, &events, &reveal) {
+                        Ok(()) => println!("Deck verified"),
+                        Err(error) => eprintln!("Could not verify the deck: {}", error)
+                    }
+                    events.clear();
+                },
+                Event::Snapshot(_) => events.clear(),
+                Event::MatchEnd(result, wins) => {
+                    println!("Match over: {:?} {:?}", result, wins);
+                    return Ok(());
+                },
+                Event::PlayerLeft(player, departure) if player == self.player_id => {
+                    println!("Left the game: {:?}", departure);
+                    return Ok(());
+                },
+                _ => {}
+            }
+
+            // Declaring and exchanging the trump card keep the turn, and change the table once confirmed
+            let table = (view.round, view.trick.len(), view.declared.len(), view.trump.clone());
+            if view.can_play() && last_move.as_ref() != Some(&table) {
+                last_move = Some(table);
+                let action = strategy::next_action(strategy, &view);
+                if let Err(error) = self.send_action(game_id, &action).await {
+                    eprintln!("Could not make the move {:?}: {}", action, error);
+                    if !matches!(action, Action::Play(..)) {
+                        // Play a card anyway rather than wait for a confirmation that will not come
+                        let action = Action::Play(view.seat, strategy.choose(&view));
+                        if let Err(error) = self.send_action(game_id, &action).await {
+                            eprintln!("Could not make the move {:?}: {}", action, error);
+                        }
+                    }
+                }
+            }
+        }
+        // The server closed the stream
+        Ok(())
+    }
+
+    async fn game_info(&self, game_id: &str, invite: Option<&str>) -> Result<GameInfo, Error> {
+        let mut request = self.client
+            .get(format!("{}/game/{game_id}/info", self.server))
+            .bearer_auth(&self.token);
+        if let Some(invite) = invite {
+            request = request.query(&[("invite", invite)]);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(Error::Refused(response.json().await?));
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn send_seed(&self, game_id: &str, seed: &str) -> Result<(), Error> {
+        let response = self.client
+            .put(format!("{}/game/{game_id}/seed", self.server))
+            .bearer_auth(&self.token)
+            .json(seed)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(Error::Refused(response.json().await?));
+        }
+        Ok(())
+    }
+
+    async fn send_action(&self, game_id: &str, action: &Action) -> Result<(), Error> {
+        let url = format!("{}/game/{game_id}", self.server);
+        let request = match action {
+            Action::Play(_, card) => self.client.put(url).json(card),
+            Action::ExchangeTrump(_) => self.client.put(format!("{url}/trump")),
+            Action::Declare(_, suit) => self.client.put(format!("{url}/declare")).json(suit)
+        };
+        let response = request.bearer_auth(&self.token).send().await?;
+        if !response.status().is_success() {
+            return Err(Error::Refused(response.json().await?));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use api::bot::BotLevel;
+use runner::{Error, Runner};
+use std::env;
+
+static API_URL: &str = "http://127.0.0.1:3030";
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 3 || args.len() > 5 {
+        panic!("Run command: runner [player_id] [password] [game_id] [simple|strong] [invite]")
+    }
+    let mut strategy = match args.get(3).map(String::as_str) {
+        Some("strong") => BotLevel::Strong,
+        _ => BotLevel::Simple
+    };
+
+    let runner = Runner::login(API_URL, &args[0], &args[1]).await?;
+    runner.play(&args[2], args.get(4).map(String::as_str), &mut strategy).await
+}
@@ -0,0 +1,186 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use api::ErrorCode;
+use crate::storage::Storage;
+
+
+// How long a session token is valid for
+static SESSION_SECONDS: u64 = 24 * 60 * 60;
+
+/// Start of the names of the bots filling seats. No player can register one.
+pub static BOT_PREFIX: &str = "Bot ";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Registered players and the signing of their session tokens.
+///
+/// A token is `hex(player_id).expires.hex(signature)`, signed with a secret
+/// generated the first time the server starts. The secret and the accounts are kept
+/// in storage, so tokens survive a server restart when the storage does.
+pub struct Auth {
+    secret: [u8; 32],
+    // Password hash of each registered player
+    accounts: Mutex<HashMap<String, String>>,
+    storage: Arc<dyn Storage>
+}
+
+impl Auth {
+    pub fn new(storage: Arc<dyn Storage>) -> Auth {
+        let stored = storage.secret().unwrap_or_else(|error| panic!("Could not read the secret: {}", error));
+        let secret = match stored.and_then(|secret| secret.try_into().ok()) {
+            Some(secret) => secret,
+            None => {
+                let mut secret = [0; 32];
+                thread_rng().fill_bytes(&mut secret);
+                if let Err(error) = storage.set_secret(&secret) {
+                    eprintln!("Could not store the secret: {}", error);
+                }
+                secret
+            }
+        };
+        let accounts = storage.accounts().unwrap_or_else(|error| panic!("Could not read the accounts: {}", error));
+        Auth { secret, accounts: Mutex::new(accounts.into_iter().collect()), storage }
+    }
+
+    /// Register a new player and return a session token.
+    /// Hashing the password is slow, so it is meant to run on a blocking thread.
+    pub fn register(&self, player_id: &str, password: &str) -> Result<String, ErrorCode> {
+        if player_id.is_empty() || password.is_empty() || player_id.starts_with(BOT_PREFIX) {
+            return Err(ErrorCode::InvalidCredentials);
+        }
+        if self.accounts.lock().unwrap().contains_key(player_id) {
+            return Err(ErrorCode::PlayerExists);
+        }
+
+        let salt = SaltString::generate(&mut thread_rng());
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|_| ErrorCode::InvalidCredentials)?
+            .to_string();
+
+        let mut accounts = self.accounts.lock().unwrap();
+        // Someone may have registered the same player id while the password was hashed
+        if accounts.contains_key(player_id) {
+            return Err(ErrorCode::PlayerExists);
+        }
+        if let Err(error) = self.storage.add_account(player_id, &hash) {
+            eprintln!("Could not store the account of {}: {}", player_id, error);
+            return Err(ErrorCode::ServerError);
+        }
+        accounts.insert(player_id.to_string(), hash);
+
+        Ok(self.issue(player_id))
+    }
+
+    /// Check the player's password and return a session token.
+    /// Verifying the password is slow, so it is meant to run on a blocking thread.
+    pub fn login(&self, player_id: &str, password: &str) -> Result<String, ErrorCode> {
+        let hash = self.accounts.lock().unwrap().get(player_id).cloned().ok_or(ErrorCode::InvalidCredentials)?;
+        let hash = PasswordHash::new(&hash).map_err(|_| ErrorCode::InvalidCredentials)?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .map_err(|_| ErrorCode::InvalidCredentials)?;
+
+        Ok(self.issue(player_id))
+    }
+
+    /// Player id of a valid and unexpired session token.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let (player_id, expires) = payload.split_once('.')?;
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&hex::decode(signature).ok()?).ok()?;
+
+        if expires.parse::<u64>().ok()? < now() {
+            return None;
+        }
+        String::from_utf8(hex::decode(player_id).ok()?).ok()
+    }
+
+    fn issue(&self, player_id: &str) -> String {
+        self.sign(player_id, now() + SESSION_SECONDS)
+    }
+
+    // Token of the player valid until the given time, in seconds since the Unix epoch
+    fn sign(&self, player_id: &str, expires: u64) -> String {
+        let payload = format!("{}.{}", hex::encode(player_id), expires);
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        format!("{}.{}", payload, hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).unwrap()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn registered_players_are_given_tokens_of_their_own() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let auth = Auth::new(storage.clone());
+        let token = auth.register("ann", "secret").unwrap();
+        assert_eq!(auth.verify(&token), Some("ann".to_string()));
+
+        let token = auth.login("ann", "secret").unwrap();
+        assert_eq!(auth.verify(&token), Some("ann".to_string()));
+        assert_eq!(auth.login("ann", "wrong"), Err(ErrorCode::InvalidCredentials));
+        // The secret and the accounts are kept in storage
+        let restarted = Auth::new(storage);
+        assert_eq!(restarted.verify(&token), Some("ann".to_string()));
+        assert!(restarted.login("ann", "secret").is_ok());
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let auth = Auth::new(Arc::new(MemoryStorage::default()));
+        let token = auth.register("ann", "secret").unwrap();
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+
+        // Someone else's player id with ann's signature
+        let forged = payload.replacen(&hex::encode("ann"), &hex::encode("bob"), 1);
+        assert_eq!(auth.verify(&format!("{}.{}", forged, signature)), None);
+        // A later expiry
+        let (player_id, _) = payload.split_once('.').unwrap();
+        assert_eq!(auth.verify(&format!("{}.{}.{}", player_id, u64::MAX, signature)), None);
+        // Another signature
+        let other = if signature.starts_with('0') { "1" } else { "0" };
+        assert_eq!(auth.verify(&format!("{}.{}{}", payload, other, &signature[1..])), None);
+        assert_eq!(auth.verify(payload), None);
+        // Signed with the secret of another server
+        let other_server = Auth::new(Arc::new(MemoryStorage::default()));
+        assert_eq!(other_server.verify(&token), None);
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let auth = Auth::new(Arc::new(MemoryStorage::default()));
+        assert_eq!(auth.verify(&auth.sign("ann", now() - 1)), None);
+        assert_eq!(auth.verify(&auth.sign("ann", now() + 60)), Some("ann".to_string()));
+    }
+
+    #[test]
+    fn bot_names_and_taken_names_cannot_be_registered() {
+        let auth = Auth::new(Arc::new(MemoryStorage::default()));
+        assert_eq!(auth.register(&format!("{}1", BOT_PREFIX), "secret"), Err(ErrorCode::InvalidCredentials));
+        assert!(auth.register("ann", "secret").is_ok());
+        assert_eq!(auth.register("ann", "other"), Err(ErrorCode::PlayerExists));
+        assert_eq!(auth.login("ann", "other"), Err(ErrorCode::InvalidCredentials));
+    }
+}
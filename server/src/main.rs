@@ -1,125 +1,363 @@
+mod auth;
+mod storage;
+use auth::Auth;
+use storage::{MemoryStorage, Move, Recorder, SqliteStorage, Storage, StoredGame};
+
 use futures_util::{Stream, StreamExt};
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::env;
 use std::sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use warp::{sse, Filter};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-use api::{self, Card, GameInfo, Suit};
+use warp::{http::StatusCode, sse, Filter, Reply};
+use rand::{thread_rng, RngCore};
+use api::{self, Card, Credentials, Departure, ErrorCode, ErrorResponse, EventEnvelope, GameConfig, GameInfo, GameQuery, GameResult, GameStatus, GameSnapshot, LeavePolicy, NewGame, Session, Suit, TimeoutPolicy};
+use api::bot::{self, BotLevel};
+use api::fair;
+use api::rules::{self, Action, MatchState, Outcome, Phase, RuleError};
 
 
 /// Our global unique game id counter.
 static NEXT_GAME_ID: AtomicUsize = AtomicUsize::new(1);
-// Deck of cards
-static CARDS: [Card; 40] = [
-    Card {number: 1, suit: Suit::Coin},
-    Card {number: 2, suit: Suit::Coin},
-    Card {number: 3, suit: Suit::Coin},
-    Card {number: 4, suit: Suit::Coin},
-    Card {number: 5, suit: Suit::Coin},
-    Card {number: 6, suit: Suit::Coin},
-    Card {number: 7, suit: Suit::Coin},
-    Card {number: 10, suit: Suit::Coin},
-    Card {number: 11, suit: Suit::Coin},
-    Card {number: 12, suit: Suit::Coin},
-    Card {number: 1, suit: Suit::Cup},
-    Card {number: 2, suit: Suit::Cup},
-    Card {number: 3, suit: Suit::Cup},
-    Card {number: 4, suit: Suit::Cup},
-    Card {number: 5, suit: Suit::Cup},
-    Card {number: 6, suit: Suit::Cup},
-    Card {number: 7, suit: Suit::Cup},
-    Card {number: 10, suit: Suit::Cup},
-    Card {number: 11, suit: Suit::Cup},
-    Card {number: 12, suit: Suit::Cup},
-    Card {number: 1, suit: Suit::Baton},
-    Card {number: 2, suit: Suit::Baton},
-    Card {number: 3, suit: Suit::Baton},
-    Card {number: 4, suit: Suit::Baton},
-    Card {number: 5, suit: Suit::Baton},
-    Card {number: 6, suit: Suit::Baton},
-    Card {number: 7, suit: Suit::Baton},
-    Card {number: 10, suit: Suit::Baton},
-    Card {number: 11, suit: Suit::Baton},
-    Card {number: 12, suit: Suit::Baton},
-    Card {number: 1, suit: Suit::Sword},
-    Card {number: 2, suit: Suit::Sword},
-    Card {number: 3, suit: Suit::Sword},
-    Card {number: 4, suit: Suit::Sword},
-    Card {number: 5, suit: Suit::Sword},
-    Card {number: 6, suit: Suit::Sword},
-    Card {number: 7, suit: Suit::Sword},
-    Card {number: 10, suit: Suit::Sword},
-    Card {number: 11, suit: Suit::Sword},
-    Card {number: 12, suit: Suit::Sword},
-];
-
-#[derive(Clone, Debug, Default)]
-struct GameState {
-    deck: Vec<Card>,
-    played: Vec<Card>,
-    turn: u8,
-    round: u8,
-    trump: Card
-}
-
-#[derive(Debug, Default, Clone)]
+
+// How long a game can go without activity before it is abandoned, and how long
+// a finished or abandoned game is kept around. Overridden with GAME_TTL_SECONDS
+static DEFAULT_GAME_TTL_SECONDS: u64 = 30 * 60;
+// How often idle games and dropped event streams are looked for
+static SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+// How long a player can stay disconnected before they are considered gone
+static DISCONNECT_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
 struct Game {
-    num_players: u8,
+    id: usize,
+    config: GameConfig,
+    // Invite code required to join a private game
+    invite: Option<String>,
     players: Vec<Player>,
-    state: GameState
+    state: MatchState,
+    status: GameStatus,
+    created: SystemTime,
+    last_activity: Instant,
+    // Game of the match, round and cards played when the current turn started
+    turn_key: Option<(u8, u8, u8)>,
+    // When the current turn runs out, if the game has a turn time limit
+    deadline: Option<SystemTime>,
+    // The players were asked for their seeds of the next deck
+    seeds_requested: bool,
+    // Result of the match once it is over
+    result: Option<GameResult>
+}
+
+impl Game {
+    // New game waiting for players, its bots already seated
+    fn new(id: usize, config: GameConfig, invite: Option<String>, state: MatchState, created: SystemTime) -> Game {
+        let players = (1..=config.bots)
+            .map(|i| Player::computer(format!("{}{}", auth::BOT_PREFIX, i), config.bot_level.clone()))
+            .collect();
+        Game {
+            id,
+            config,
+            invite,
+            players,
+            state,
+            status: GameStatus::Waiting,
+            created,
+            last_activity: Instant::now(),
+            turn_key: None,
+            deadline: None,
+            seeds_requested: false,
+            result: None
+        }
+    }
+
+    fn info(&self) -> GameInfo {
+        let mut config = self.config.clone();
+        // The seed gives away the server seed of every deck of the match
+        let over = !matches!(self.status, GameStatus::Waiting | GameStatus::InProgress);
+        config.seed = over.then_some(self.state.seed);
+        GameInfo {
+            id: self.id.to_string(),
+            num_players: self.config.num_players,
+            config,
+            players: self.players.iter().map(|p| p.id.clone()).collect(),
+            left: self.players.iter().filter(|p| p.has_left()).map(|p| p.id.clone()).collect(),
+            created: self.created.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            status: self.status.clone()
+        }
+    }
+
+    // What is kept of the game in storage besides its moves
+    fn stored(&self) -> StoredGame {
+        StoredGame {
+            id: self.id,
+            config: self.config.clone(),
+            invite: self.invite.clone(),
+            seed: self.state.seed,
+            created: self.created.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            status: self.status.clone(),
+            result: self.result.clone(),
+            wins: self.state.wins.clone()
+        }
+    }
+
+    // Public games listed in the lobby for the query
+    fn is_listed(&self, query: &GameQuery) -> bool {
+        self.invite.is_none()
+            && query.status.matches(&self.status)
+            && query.num_players.is_none_or(|num_players| num_players == self.config.num_players)
+            && query.variant.as_ref().is_none_or(|variant| *variant == self.config.rules.variant)
+    }
+
+    // Public games can be joined by anyone
+    fn is_invited(&self, invite: Option<&String>) -> bool {
+        self.invite.is_none() || self.invite.as_ref() == invite
+    }
+
+    // Log the event for the player sitting at the seat and send it to them
+    fn send(&mut self, seat: usize, event: api::Event) {
+        let player = &mut self.players[seat];
+        let envelope = EventEnvelope {
+            game_id: self.id.to_string(),
+            seq: player.events.len() as u64 + 1,
+            event
+        };
+        // Events to a disconnected player are dropped. They are replayed when they reconnect
+        if let Some(sender) = &player.sender {
+            let _ = sender.send(envelope.clone());
+        }
+        player.events.push(envelope);
+    }
+
+    // End the event stream of every player once they have received all pending events
+    fn close_streams(&mut self) {
+        for player in &mut self.players {
+            player.sender = None;
+        }
+    }
+
+    fn broadcast(&mut self, event: api::Event) {
+        for seat in 0..self.players.len() {
+            self.send(seat, event.clone());
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 struct Player {
     id: String,
-    cards: Vec<Card>,
-    score: u8,
-    sender: UnboundedSender<api::Event>
+    // Closed once the player has left or the game has finished
+    sender: Option<UnboundedSender<EventEnvelope>>,
+    // Every event sent to this player, in order
+    events: Vec<EventEnvelope>,
+    // A bot plays for the player, since the game was created or since they left the game
+    bot: Option<BotLevel>,
+    // Seat filled with a bot when the game was created. No player can take it
+    computer: bool,
+    // When the player's event stream was found closed
+    disconnected: Option<Instant>,
+    // How the player left the game in progress, if they did
+    departed: Option<Departure>,
+    // Seed sent for the next deck, mixed into its shuffle
+    seed: Option<String>
+}
+
+impl Player {
+    fn new(id: String, sender: Option<UnboundedSender<EventEnvelope>>) -> Player {
+        Player { id, sender, events: Vec::new(), bot: None, computer: false, disconnected: None, departed: None, seed: None }
+    }
+
+    fn computer(id: String, level: BotLevel) -> Player {
+        Player { id, sender: None, events: Vec::new(), bot: Some(level), computer: true, disconnected: None, departed: None, seed: None }
+    }
+
+    // Forfeited or ran out of time. Only players that lost their connection can take their seat back
+    fn has_left(&self) -> bool {
+        self.departed.as_ref().is_some_and(|departure| *departure != Departure::Disconnected)
+    }
+
+    // Send again the events following the given sequence number
+    fn replay(&self, from: usize) {
+        if let Some(sender) = &self.sender {
+            for envelope in &self.events[from..] {
+                let _ = sender.send(envelope.clone());
+            }
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
 
-    // Registry of all games
-    let games: Arc<Mutex<HashMap<usize, Game>>> = Arc::new(Mutex::new(HashMap::new()));
+    // Games, players and their moves are kept in the SQLite database at DATABASE_PATH, if set,
+    // so that they survive a restart. Otherwise they only live in memory
+    let storage: Arc<dyn Storage> = match env::var("DATABASE_PATH") {
+        Ok(path) => match SqliteStorage::open(&path) {
+            Ok(storage) => Arc::new(storage),
+            Err(error) => panic!("Could not open database {}: {}", path, error)
+        },
+        Err(_) => Arc::new(MemoryStorage::default())
+    };
+    match storage.last_game_id() {
+        Ok(game_id) => NEXT_GAME_ID.store(game_id + 1, Ordering::Relaxed),
+        Err(error) => panic!("Could not read the last game id: {}", error)
+    }
+
+    // Registry of all games, starting with the ones still open when the server stopped
+    let games: Arc<Mutex<HashMap<usize, Game>>> = Arc::new(Mutex::new(restore(storage.as_ref())));
+    // Games and their moves are written on a thread of their own
+    let recorder = Recorder::start(storage.clone());
+    for game in games.lock().unwrap().values() {
+        if game.status == GameStatus::InProgress && game.config.turn_seconds.is_some() {
+            tokio::spawn(turn_timer(games.clone(), recorder.clone(), game.id));
+        }
+    }
+    // Clean up finished and idle games in the background
+    let ttl = env::var("GAME_TTL_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_GAME_TTL_SECONDS);
+    tokio::spawn(evict_games(games.clone(), recorder.clone(), Duration::from_secs(ttl)));
     // Turn our "state" into a new Filter...
     let games = warp::any().map(move || games.clone());
 
+    // Registered players and their sessions
+    let auth = Arc::new(Auth::new(storage));
+    let recorder = warp::any().map(move || recorder.clone());
+    let auth = warp::any().map(move || auth.clone());
+
+    // Player id of the session token in the authorization header, if valid
+    let authenticated = warp::header::optional::<String>("authorization")
+        .and(auth.clone())
+        .map(|header: Option<String>, auth: Arc<Auth>| {
+            let token = header?;
+            auth.verify(token.strip_prefix("Bearer ").unwrap_or(&token))
+        });
 
-    // POST /game/:num_players -> create a game and return game_id
+    // POST /register -> register a new player and return a session
+    let register = warp::path("register")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(auth.clone())
+        .then(|credentials: Credentials, auth: Arc<Auth>| async move {
+            // Hashing the password is slow. Keep it off the threads serving other requests
+            let player_id = credentials.player_id.clone();
+            let registered = tokio::task::spawn_blocking(move || auth.register(&player_id, &credentials.password)).await;
+            match registered.unwrap_or(Err(ErrorCode::ServerError)) {
+                Ok(token) => {
+                    println!("{} registered", credentials.player_id);
+                    warp::reply::json(&Session { player_id: credentials.player_id, token }).into_response()
+                },
+                Err(error) => error_reply(error)
+            }
+        });
+
+    // POST /login -> start a new session for a registered player
+    let login = warp::path("login")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(auth.clone())
+        .then(|credentials: Credentials, auth: Arc<Auth>| async move {
+            let player_id = credentials.player_id.clone();
+            let logged_in = tokio::task::spawn_blocking(move || auth.login(&player_id, &credentials.password)).await;
+            match logged_in.unwrap_or(Err(ErrorCode::ServerError)) {
+                Ok(token) => warp::reply::json(&Session { player_id: credentials.player_id, token }).into_response(),
+                Err(error) => error_reply(error)
+            }
+        });
+
+
+    // POST /game -> create a game with the given configuration and return game_id
     let create = warp::path("game")
         .and(warp::post())
-        .and(warp::path::param::<u8>())
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::body::json())
         .and(games.clone())
-        .map(|num_players: u8, games: Arc<Mutex<HashMap<usize, Game>>>| {
+        .and(recorder.clone())
+        .map(|player_id: Option<String>, config: GameConfig, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            if player_id.is_none() {
+                return error_reply(ErrorCode::Unauthorized);
+            }
+            if config.match_length == 0 || config.turn_seconds == Some(0) || config.bots >= config.num_players {
+                return error_reply(ErrorCode::InvalidConfig);
+            }
+            let seed = config.seed.unwrap_or_else(|| thread_rng().next_u64());
+            let state = match MatchState::new(config.num_players, config.rules.clone(), config.match_length, seed) {
+                Ok(state) => state,
+                Err(error) => return error_reply(error)
+            };
             // Generate new game_id
             let game_id = NEXT_GAME_ID.fetch_add(1, Ordering::Relaxed);
+            // Private games can only be joined with their invite code
+            let invite = config.private.then(|| {
+                let mut secret = [0; 16];
+                thread_rng().fill_bytes(&mut secret);
+                format!("{}-{}", game_id, hex::encode(secret))
+            });
             // Create new game and add to registry
-            games.lock().unwrap().insert(game_id, Game { num_players, ..Default::default() });
-            println!("Game {}: Created with num_players {}", game_id, num_players);
-            // Return game_id to user
-            game_id.to_string()
+            println!("Game {}: Created with seed {} and {:?}", game_id, seed, config);
+            let game = Game::new(game_id, config, invite.clone(), state, SystemTime::now());
+            recorder.add_game(game.stored());
+            games.lock().unwrap().insert(game_id, game);
+            // Return game_id and invite code to user
+            warp::reply::json(&NewGame { id: game_id.to_string(), invite }).into_response()
+        });
+
+    // GET /game/:game_id/info?invite=code -> get the configuration of a game
+    let info = warp::path("game")
+        .and(warp::get())
+        .and(warp::path::param::<usize>())
+        .and(warp::path("info"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(games.clone())
+        .map(|game_id, player_id: Option<String>, query: HashMap<String, String>, games: Arc<Mutex<HashMap<usize, Game>>>| {
+            if player_id.is_none() {
+                return error_reply(ErrorCode::Unauthorized);
+            }
+            let games = games.lock().unwrap();
+            let Some(game) = games.get(&game_id) else {
+                return error_reply(ErrorCode::GameNotFound);
+            };
+            if !game.is_invited(query.get("invite")) {
+                return error_reply(ErrorCode::InvalidInvite);
+            }
+            warp::reply::json(&game.info()).into_response()
         });
 
-    // GET /game/:game_id -> join game and get event stream
+    // GET /game/:game_id?invite=code -> join game and get event stream
+    // Players reconnecting with the Last-Event-ID header get the events they missed
     let join = warp::path("game")
         .and(warp::get())
         .and(warp::path::param::<usize>())
-        .and(warp::header::<String>("authorization")) // TODO: Implement proper auth
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<u64>("last-event-id"))
         .and(games.clone())
-        .map(|game_id, player_id, games: Arc<Mutex<HashMap<usize, Game>>>| {
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, query: HashMap<String, String>, last_event_id: Option<u64>, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
             // Get game
-            // TODO: Sanity check that game exists
+            let registry = games.clone();
             let mut games = games.lock().unwrap();
-            let game = games.get_mut(&game_id).unwrap();
+            let Some(game) = games.get_mut(&game_id) else {
+                return error_reply(ErrorCode::GameNotFound);
+            };
 
-            if game.num_players == game.players.len() as u8 {
-                // TODO: Sanity check that the game is not full / has started already
-                eprintln!("Attempting to join an ongoing game.")
+            if !game.is_invited(query.get("invite")) {
+                return error_reply(ErrorCode::InvalidInvite);
             }
-            println!("Game {}: {} joined the game", game_id, player_id);
+            if game.status == GameStatus::Abandoned {
+                return error_reply(ErrorCode::GameFinished);
+            }
+            game.last_activity = Instant::now();
 
             // Create player channel game event stream
             // Use an unbounded channel to handle buffering and flushing of messages
@@ -127,203 +365,845 @@ async fn main() {
             let (tx, rx) = unbounded_channel();
             let rx = UnboundedReceiverStream::new(rx);
 
-            // Create Player
-            let player = Player {
-                id: player_id,
-                cards: Vec::new(),
-                score: 0,
-                sender: tx.clone()
-            };
-
-            // Add player to game registry
-            game.players.push(player);
+            if let Some(seat) = game.players.iter().position(|p| p.id == player_id && !p.computer) {
+                // A seated player reconnecting. Replace their event stream and resync their state
+                if game.players[seat].has_left() {
+                    return error_reply(ErrorCode::PlayerLeft);
+                }
+                println!("Game {}: {} reconnected", game_id, player_id);
+                game.players[seat].sender = Some(tx);
+                let missed = last_event_id.filter(|&id| id as usize <= game.players[seat].events.len());
+                // Only a snapshot or a bot giving the seat back changes the game
+                let rejoined = missed.is_none() || game.players[seat].bot.is_some();
+                take_seat(game, seat, missed.is_none());
+                if let Some(id) = missed {
+                    // Replay the events the player missed
+                    game.players[seat].replay(id as usize);
+                }
+                if rejoined {
+                    record(&recorder, game, Move::Rejoined(seat, missed.is_none()));
+                }
+                if game.status == GameStatus::Finished || game.status == GameStatus::Cancelled {
+                    // Nothing else will be sent. End the stream once caught up
+                    game.players[seat].sender = None;
+                }
 
-            // Get list of players
-            let players: Vec<String> = game.players.iter().map(|p| p.id.clone()).collect();
-            // Send to all players the updated list of players
-            for player in &game.players {
-                player.sender.send(api::Event::Connected(players.clone())).unwrap();
+                let stream = create_sse_stream(rx);
+                return warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response();
+            }
+            if game.config.num_players == game.players.len() as u8 {
+                return error_reply(ErrorCode::GameFull);
             }
+            println!("Game {}: {} joined the game", game_id, player_id);
 
-            // If all needed players have joined, start the game.
-            if game.num_players == game.players.len() as u8 {
-                println!("Game {}: All {} players have joined. Start Game", game_id, game.num_players);
-                // Break out new deck of cards and shuffle them
-                let mut rng = thread_rng();
-                game.state.deck = CARDS.to_vec();
-                game.state.deck.shuffle(&mut rng);
-                // Get the trump card from the top of the deck
-                // The trump card should stay in the deck as the last card to be dealt
-                game.state.trump  = game.state.deck[0].clone();
-
-                for player in &mut game.players {
-                    // Deal 3 cards to each player
-                    for _ in 0..3 {
-                        let card = game.state.deck.pop().unwrap();
-                        player.cards.push(card.clone());
-                        player.sender.send(api::Event::NewCard(card)).unwrap();
-                    }
-                    player.sender.send(api::Event::GameStart(game.state.trump.clone())).unwrap();
-                }
+            // Create Player and add them to the game, starting it if all needed players have joined
+            let player = Player::new(player_id.clone(), Some(tx));
+            seat_player(game, player);
+            record(&recorder, game, Move::Joined(player_id));
+            // Only the last player to join can start the game
+            if game.status == GameStatus::InProgress && game.config.turn_seconds.is_some() {
+                tokio::spawn(turn_timer(registry, recorder, game_id));
             }
 
             // Convert messages into Server-Sent Events and return resulting stream.
             let stream = create_sse_stream(rx);
 
             // Return game stream
-            warp::sse::reply(warp::sse::keep_alive().stream(stream))
+            warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response()
+        });
+
+    // GET /game/:game_id/state -> get the current state of the game as seen by the player
+    let state = warp::path("game")
+        .and(warp::get())
+        .and(warp::path::param::<usize>())
+        .and(warp::path("state"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(games.clone())
+        .map(|game_id, player_id: Option<String>, games: Arc<Mutex<HashMap<usize, Game>>>| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
+            let games = games.lock().unwrap();
+            let Some(game) = games.get(&game_id) else {
+                return error_reply(ErrorCode::GameNotFound);
+            };
+            // Only seated players can see the game. Their opponents' hands stay hidden
+            let Some(seat) = game.players.iter().position(|p| p.id == player_id && !p.computer) else {
+                return error_reply(ErrorCode::PlayerNotFound);
+            };
+            warp::reply::json(&snapshot(game, seat as u8)).into_response()
+        });
+
+    // DELETE /game/:game_id -> leave a game that has not started yet
+    let leave = warp::path("game")
+        .and(warp::delete())
+        .and(warp::path::param::<usize>())
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(games.clone())
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
+
+            leave_game(&games, &recorder, game_id, &player_id, Departure::Left)
+        });
+
+    // PUT /game/:game_id/forfeit -> give up a game in progress
+    let forfeit = warp::path("game")
+        .and(warp::put())
+        .and(warp::path::param::<usize>())
+        .and(warp::path("forfeit"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(games.clone())
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
+
+            leave_game(&games, &recorder, game_id, &player_id, Departure::Forfeited)
+        });
+
+    // PUT /game/:game_id/seed -> send our seed of the next deck, once asked for it with SeedRequested
+    let send_seed = warp::path("game")
+        .and(warp::put())
+        .and(warp::path::param::<usize>())
+        .and(warp::path("seed"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::body::json())
+        .and(games.clone())
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, seed: String, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
+            println!("Game {}: {} sends the seed {:?}", game_id, player_id, seed);
+
+            seed_deck(&games, &recorder, game_id, &player_id, seed)
+        });
+
+    // PUT /game/:game_id/trump -> exchange the trump card
+    let exchange_trump = warp::path("game")
+        .and(warp::put())
+        .and(warp::path::param::<usize>())
+        .and(warp::path("trump"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(games.clone())
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
+            println!("Game {}: {} exchanges the trump card", game_id, player_id);
+
+            apply_action(&games, &recorder, game_id, &player_id, Action::ExchangeTrump)
+        });
+
+    // PUT /game/:game_id/declare -> declare the king and horse of a suit
+    let declare = warp::path("game")
+        .and(warp::put())
+        .and(warp::path::param::<usize>())
+        .and(warp::path("declare"))
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::body::json())
+        .and(games.clone())
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, suit: Suit, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
+            println!("Game {}: {} declares {:?}", game_id, player_id, suit);
+
+            apply_action(&games, &recorder, game_id, &player_id, |seat| Action::Declare(seat, suit))
         });
 
     // PUT /game/:game_id -> play card
     let play = warp::path("game")
         .and(warp::put())
         .and(warp::path::param::<usize>())
-        .and(warp::header::<String>("authorization")) // TODO: Implement proper auth
+        .and(warp::path::end())
+        .and(authenticated.clone())
         .and(warp::body::json())
         .and(games.clone())
-        .map(|game_id, player_id: String, card: Card, games: Arc<Mutex<HashMap<usize, Game>>>| {
+        .and(recorder.clone())
+        .map(|game_id, player_id: Option<String>, card: Card, games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder| {
+            let Some(player_id) = player_id else {
+                return error_reply(ErrorCode::Unauthorized);
+            };
             println!("Game {}: {} plays {:?}", game_id, player_id, card);
 
-            // Get game
-            // TODO: Sanity check that game exists
-            let mut games = games.lock().unwrap();
-            let game = games.get_mut(&game_id).unwrap();
+            apply_action(&games, &recorder, game_id, &player_id, |seat| Action::Play(seat, card))
+        });
 
-            let player_idx = game.players.iter().position(|p| *p.id == player_id).unwrap();
-            if player_idx as u8 != game.state.turn {
-                // TODO: Sanity check that it is currently that players turn
+    // GET /game?status=waiting&num_players=2&variant=Classic&offset=0&limit=50 -> List public games
+    let list_games = warp::path("game")
+        .and(warp::get())
+        .and(warp::path::end())
+        .and(authenticated.clone())
+        .and(warp::query::<GameQuery>())
+        .and(games.clone())
+        .map(|player_id: Option<String>, query: GameQuery, games: Arc<Mutex<HashMap<usize, Game>>>| {
+            if player_id.is_none() {
+                return error_reply(ErrorCode::Unauthorized);
             }
+            let games = games.lock().unwrap();
+            let mut listed: Vec<&Game> = games
+                .values()
+                .filter(|game| game.is_listed(&query))
+                .collect();
+            // Game ids are given out in creation order
+            listed.sort_by_key(|game| game.id);
+            let page: Vec<GameInfo> = listed
+                .into_iter()
+                .skip(query.offset)
+                .take(query.limit)
+                .map(Game::info)
+                .collect();
+            warp::reply::json(&page).into_response()
+        });
 
-            // Remove card from player's hand
-            game.players[player_idx].cards.retain(|c| *c != card);
-            // TODO: Sanity check that the card exists in his hand
+    let routes = register
+        .or(login)
+        .or(create)
+        .or(info)
+        .or(join)
+        .or(state)
+        .or(leave)
+        .or(forfeit)
+        .or(send_seed)
+        .or(exchange_trump)
+        .or(declare)
+        .or(play)
+        .or(list_games)
+        .recover(handle_rejection);
 
-            // Update other player with card played
-            for i in 0..game.players.len() {
-                if i != player_idx {
-                    game.players[i].sender.send(api::Event::PlayedCard(card.clone())).unwrap();
-                }
+    warp::serve(routes)
+        .run(([127, 0, 0, 1], 3030))
+        .await;
+}
+
+
+
+fn create_sse_stream(rx: UnboundedReceiverStream<EventEnvelope>) -> impl Stream<Item = Result<sse::Event, warp::Error>> + Send + 'static {
+    // Transforms API events to SSE event, using the sequence number as the event id
+    rx.map(|envelope| Ok(sse::Event::default()
+        .id(envelope.seq.to_string())
+        .data(serde_json::to_string(&envelope).unwrap())))
+}
+
+fn apply_action(games: &Mutex<HashMap<usize, Game>>, recorder: &Recorder, game_id: usize, player_id: &str, action: impl FnOnce(u8) -> Action) -> warp::reply::Response {
+    // Get game
+    let mut games = games.lock().unwrap();
+    let Some(game) = games.get_mut(&game_id) else {
+        return error_reply(ErrorCode::GameNotFound);
+    };
+
+    let Some(player_idx) = game.players.iter().position(|p| p.id == player_id && p.bot.is_none()) else {
+        return error_reply(ErrorCode::PlayerNotFound);
+    };
+    // A match called off can still have a game being played
+    match game.status {
+        GameStatus::InProgress => {},
+        GameStatus::Waiting => return error_reply(ErrorCode::GameNotStarted),
+        _ => return error_reply(ErrorCode::GameFinished)
+    }
+
+    let action = action(player_idx as u8);
+    if let Err(error) = play_action(game, action.clone()) {
+        eprintln!("Game {}: {} could not play: {}", game_id, player_id, error);
+        return error_reply(error);
+    }
+    game.last_activity = Instant::now();
+    record(recorder, game, Move::Action(action));
+
+    warp::reply().into_response()
+}
+
+// Apply the move to the game rules, notify players of the outcome and carry the match on
+fn play_action(game: &mut Game, action: Action) -> Result<(), RuleError> {
+    let events = game.state.apply(action)?;
+    dispatch(game, events);
+    advance(game);
+    Ok(())
+}
+
+// Seat a new player and start the game once every seat is taken
+fn seat_player(game: &mut Game, player: Player) {
+    game.players.push(player);
+
+    // Send to all players the updated list of players
+    let players: Vec<String> = game.players.iter().map(|p| p.id.clone()).collect();
+    game.broadcast(api::Event::Connected(players));
+
+    if game.config.num_players == game.players.len() as u8 {
+        println!("Game {}: All {} players have joined. Start Game", game.id, game.config.num_players);
+        game.status = GameStatus::InProgress;
+        advance(game);
+    }
+}
+
+// A seated player reconnecting takes their seat back from the bot, if any,
+// and is sent the state of the game if they cannot catch up with the events they missed
+fn take_seat(game: &mut Game, seat: usize, resync: bool) {
+    game.players[seat].bot = None;
+    game.players[seat].disconnected = None;
+    game.players[seat].departed = None;
+    if resync {
+        let snapshot = snapshot(game, seat as u8);
+        game.send(seat, api::Event::Snapshot(snapshot));
+    }
+}
+
+fn seed_deck(games: &Mutex<HashMap<usize, Game>>, recorder: &Recorder, game_id: usize, player_id: &str, seed: String) -> warp::reply::Response {
+    // Get game
+    let mut games = games.lock().unwrap();
+    let Some(game) = games.get_mut(&game_id) else {
+        return error_reply(ErrorCode::GameNotFound);
+    };
+
+    let Some(seat) = game.players.iter().position(|p| p.id == player_id && p.bot.is_none()) else {
+        return error_reply(ErrorCode::PlayerNotFound);
+    };
+    if seed.len() > fair::MAX_CLIENT_SEED_LENGTH {
+        return error_reply(ErrorCode::InvalidSeed);
+    }
+    match game.status {
+        GameStatus::InProgress => {},
+        GameStatus::Waiting => return error_reply(ErrorCode::GameNotStarted),
+        _ => return error_reply(ErrorCode::GameFinished)
+    }
+    // One seed per deck, sent after the server committed to its own
+    if !game.seeds_requested || game.players[seat].seed.is_some() {
+        return error_reply(ErrorCode::SeedNotRequested);
+    }
+
+    game.last_activity = Instant::now();
+    take_seed(game, seat, seed.clone());
+    record(recorder, game, Move::Seeded(seat, seed));
+
+    warp::reply().into_response()
+}
+
+// Keep the seed of the player sitting at seat for the next deck, dealing it once every seed is in
+fn take_seed(game: &mut Game, seat: usize, seed: String) {
+    game.players[seat].seed = Some(seed);
+    advance(game);
+}
+
+// Keep a move of the game and its status, so the game can be restored after a restart
+fn record(recorder: &Recorder, game: &Game, game_move: Move) {
+    recorder.add_move(game_move, game.stored());
+}
+
+// Games still open when the server stopped, brought back by replaying their moves.
+// Players have to reconnect to their seats before they are considered gone
+fn restore(storage: &dyn Storage) -> HashMap<usize, Game> {
+    let mut games = HashMap::new();
+    let open = match storage.open_games() {
+        Ok(open) => open,
+        Err(error) => {
+            eprintln!("Could not restore games: {}", error);
+            return games;
+        }
+    };
+    for (stored, moves) in open {
+        let config = stored.config;
+        let state = match MatchState::new(config.num_players, config.rules.clone(), config.match_length, stored.seed) {
+            Ok(state) => state,
+            Err(error) => {
+                eprintln!("Game {}: Could not restore: {}", stored.id, error);
+                continue;
             }
+        };
+        let created = UNIX_EPOCH + Duration::from_secs(stored.created);
+        let mut game = Game::new(stored.id, config, stored.invite, state, created);
+        for game_move in moves {
+            replay(&mut game, game_move);
+        }
+        println!("Game {}: Restored {:?} with {} players", game.id, game.status, game.players.len());
+        games.insert(game.id, game);
+    }
+    games
+}
 
-            // Save card played
-            game.state.played.push(card);
-
-            // Check if all players have played
-            if game.state.played.len() == game.players.len() {
-                // End of the round. Compute result.
-
-                let mut winner_idx: u8 = 0;
-                let mut score = 0;
-                // First card played dictates the round's winning suit, unless another player plays card with the trump suit
-                let mut best_card = &game.state.played[0];
-                // Look-up table of points values awarded for each card number value
-                // Map to the following numbers [x, 1,  2,  3, 4, 5, 6, 7, x, x, 10, 11, 12]
-                let points_per_card = [0, 11, 0, 10, 0, 0, 0, 0, 0, 0,  2,  3,  4];
-                // Look-up table of heirarchical ordering 0..10 based on points and number
-                let order = [0, 9, 0, 8, 1, 2, 3, 4, 0, 0, 5, 6, 7];
-
-                score += points_per_card[best_card.number as usize];
-
-                for i in 1..game.state.played.len() {
-                    let card = &game.state.played[i];
-                    score += points_per_card[card.number as usize];
-
-                    if card.suit == best_card.suit {
-                        if order[card.number as usize] > order[best_card.number as usize] {
-                            best_card = card;
-                            winner_idx = i as u8;
-                        }
-                    } else if card.suit == game.state.trump.suit {
-                        best_card = card;
-                        winner_idx = i as u8;
-                    }
-                }
+fn replay(game: &mut Game, game_move: Move) {
+    let seats = game.players.len();
+    match game_move {
+        Move::Joined(player_id) if seats < game.config.num_players as usize => {
+            seat_player(game, Player::new(player_id, None));
+        },
+        Move::Rejoined(seat, resync) if seat < seats => take_seat(game, seat, resync),
+        Move::Seeded(seat, seed) if seat < seats => take_seed(game, seat, seed),
+        Move::Action(action) => {
+            if let Err(error) = play_action(game, action) {
+                eprintln!("Game {}: Could not replay a move: {}", game.id, error);
+            }
+        },
+        Move::Departed(seat, departure) if seat < seats => depart(game, seat, departure),
+        Move::TimedOut if game.status == GameStatus::InProgress => time_out(game),
+        game_move => eprintln!("Game {}: Could not replay {:?}", game.id, game_move)
+    }
+}
 
-                // Shift based on which player started the round (turn + 1)
-                winner_idx = (winner_idx + game.state.turn + 1) % game.players.len() as u8;
+fn leave_game(games: &Mutex<HashMap<usize, Game>>, recorder: &Recorder, game_id: usize, player_id: &str, departure: Departure) -> warp::reply::Response {
+    // Get game
+    let mut games = games.lock().unwrap();
+    let Some(game) = games.get_mut(&game_id) else {
+        return error_reply(ErrorCode::GameNotFound);
+    };
 
-                for player in &game.players {
-                    player.sender.send(api::Event::RoundEnd(winner_idx, score)).unwrap();
-                }
+    let Some(seat) = game.players.iter().position(|p| p.id == player_id && p.bot.is_none()) else {
+        return error_reply(ErrorCode::PlayerNotFound);
+    };
+    // Players can only leave the lobby, and forfeit once the game has started
+    match (&departure, &game.status) {
+        (Departure::Left, GameStatus::Waiting) | (Departure::Forfeited, GameStatus::InProgress) => {},
+        (Departure::Left, GameStatus::InProgress) => return error_reply(ErrorCode::GameInProgress),
+        (_, GameStatus::Waiting) => return error_reply(ErrorCode::GameNotStarted),
+        _ => return error_reply(ErrorCode::GameFinished)
+    }
+
+    game.last_activity = Instant::now();
+    depart(game, seat, departure.clone());
+    record(recorder, game, Move::Departed(seat, departure));
+
+    warp::reply().into_response()
+}
+
+// Remove the player sitting at seat and resolve the game according to its leave policy
+fn depart(game: &mut Game, seat: usize, departure: Departure) {
+    let player_id = game.players[seat].id.clone();
+    println!("Game {}: {} left the game ({:?})", game.id, player_id, departure);
+
+    // Let everyone know, the player included, before closing their stream
+    game.broadcast(api::Event::PlayerLeft(player_id, departure.clone()));
+    game.players[seat].sender = None;
+    game.players[seat].departed = Some(departure);
 
-                // Update player score
-                game.players[winner_idx as usize].score += score;
+    match game.status {
+        GameStatus::Waiting => {
+            game.players.remove(seat);
+            let players: Vec<String> = game.players.iter().map(|p| p.id.clone()).collect();
+            game.broadcast(api::Event::Connected(players));
+        },
+        GameStatus::InProgress => match game.config.on_leave {
+            LeavePolicy::OpponentsWin => match game.state.forfeit(seat as u8) {
+                Ok(events) => dispatch(game, events),
+                Err(error) => eprintln!("Game {}: Could not forfeit: {}", game.id, error)
+            },
+            LeavePolicy::Bot => game.players[seat].bot = Some(game.config.bot_level.clone()),
+            LeavePolicy::Cancel => {
+                println!("Game {}: Cancelled", game.id);
+                call_off(game, GameResult::Cancelled, GameStatus::Cancelled);
+            }
+        },
+        _ => {}
+    }
+    advance(game);
+}
 
-                // Update who plays first turn in next round based on the winner
-                game.state.turn = winner_idx;
+// End the match before it is over and let every player know
+fn call_off(game: &mut Game, result: GameResult, status: GameStatus) {
+    if game.state.hand > 0 && game.state.game.phase != Phase::Finished {
+        // Reveal the deck of the game called off
+        let totals = game.state.game.team_scores();
+        game.broadcast(api::Event::GameEnd(result.clone(), totals, game.state.reveal()));
+    }
+    let wins = game.state.wins.clone();
+    game.broadcast(api::Event::MatchEnd(result.clone(), wins));
+    game.result = Some(result);
+    game.status = status;
+    game.close_streams();
+}
 
-                // Update round counter
-                game.state.round += 1;
+// Carry the match on after a change: deal the next game, let the bots play and finish the game
+fn advance(game: &mut Game) {
+    while game.status == GameStatus::InProgress {
+        // Keep playing the match until a team has won enough games
+        if game.state.needs_deal() {
+            if !seeds_ready(game) {
+                break;
+            }
+            println!("Game {}: Dealing game {} of the match", game.id, game.state.hand + 1);
+            deal(game.id, game);
+        }
+        if game.state.is_finished() {
+            println!("Game {}: Finished", game.id);
+            game.status = GameStatus::Finished;
+            game.close_streams();
+            break;
+        }
+
+        // Let the bots play until it is a player's turn
+        let seat = game.state.game.turn;
+        let Some(level) = &game.players[seat as usize].bot else {
+            break;
+        };
+        let action = bot::choose(&game.state.game, seat, level);
+        match game.state.apply(action) {
+            Ok(events) => dispatch(game, events),
+            Err(error) => {
+                eprintln!("Game {}: Bot could not play: {}", game.id, error);
+                break;
+            }
+        }
+    }
+    start_turn(game);
+}
 
-                // Reset cards played
-                game.state.played = Vec::with_capacity(game.players.len());
+// Ask the players for their seeds of the next deck, committing to the server seed first.
+// Ready once every player has sent theirs. Seats played by bots do not need one
+fn seeds_ready(game: &mut Game) -> bool {
+    if !game.seeds_requested {
+        game.seeds_requested = true;
+        game.broadcast(api::Event::SeedRequested(game.state.seed_commitment()));
+    }
+    game.players.iter().all(|p| p.seed.is_some() || p.bot.is_some())
+}
 
-                // Check if there's more cards.
-                if game.state.deck.len() != 0 {
-                    // Deal new card to all players. Starting from the round winner
+// Play the first card of the hand of the player sitting at seat. Any card is a legal play
+fn play_first_card(game: &mut Game, seat: u8) -> bool {
+    let card = game.state.game.hands[seat as usize][0].clone();
+    match game.state.apply(Action::Play(seat, card)) {
+        Ok(events) => {
+            dispatch(game, events);
+            true
+        },
+        Err(error) => {
+            eprintln!("Game {}: Could not play for seat {}: {}", game.id, seat, error);
+            false
+        }
+    }
+}
 
-                    for i in 0..game.players.len() {
-                        let card = game.state.deck.pop().unwrap();
-                        let idx = (winner_idx as usize + i) % game.players.len();
-                        let player = &mut game.players[idx];
-                        player.cards.push(card.clone());
-                        player.sender.send(api::Event::NewCard(card)).unwrap();
-                    }
-                } else if game.state.round as usize == CARDS.len() / game.players.len() {
-                    // We've reached the last round of the game
+// Start the clock when a new turn begins, if the game has a turn time limit
+fn start_turn(game: &mut Game) {
+    let Some(seconds) = game.config.turn_seconds else {
+        return;
+    };
+    // The players have as long to send their seeds of the next deck as to play a turn
+    let seeding = game.status == GameStatus::InProgress && game.state.needs_deal();
+    if game.status != GameStatus::InProgress || (!seeding && game.state.game.phase != Phase::Playing) {
+        game.deadline = None;
+        return;
+    }
+    let state = &game.state.game;
+    // Rounds start at 1, so round 0 stands for the seeds of the next deck
+    let key = if seeding { (game.state.hand, 0, 0) } else { (game.state.hand, state.round, state.played.len() as u8) };
+    if game.turn_key == Some(key) {
+        // Still the same turn, e.g. after a declaration
+        return;
+    }
+    let turn = state.turn;
+    let deadline = SystemTime::now() + Duration::from_secs(seconds as u64);
+    game.turn_key = Some(key);
+    game.deadline = Some(deadline);
+    if !seeding {
+        game.broadcast(api::Event::TurnStarted(turn, unix_millis(deadline)));
+    }
+}
 
-                    // Find max score player
-                    let winner = game.players.iter().max_by_key(|p| p.score).unwrap();
-                    // TODO: FIXME: Edgecase - there is a draw
+// Time out the turns of a game until it is over
+async fn turn_timer(games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder, game_id: usize) {
+    loop {
+        let wait = {
+            let games = games.lock().unwrap();
+            let Some(deadline) = games.get(&game_id).and_then(|game| game.deadline) else {
+                return;
+            };
+            deadline.duration_since(SystemTime::now()).unwrap_or_default()
+        };
+        tokio::time::sleep(wait).await;
 
-                    // Send who won to all players
-                    for player in &game.players {
-                        player.sender.send(api::Event::GameEnd(winner.id.clone())).unwrap();
-                    }
+        let mut games = games.lock().unwrap();
+        let Some(game) = games.get_mut(&game_id) else {
+            return;
+        };
+        if game.status != GameStatus::InProgress {
+            return;
+        }
+        if game.deadline.is_some_and(|deadline| deadline > SystemTime::now()) {
+            // The turn was played in time and the next one has started
+            continue;
+        }
+        time_out(game);
+        record(&recorder, game, Move::TimedOut);
+    }
+}
 
-                    // TODO: Close game event streams
+fn time_out(game: &mut Game) {
+    // A new deadline is set when the next turn starts
+    game.deadline = None;
+    game.last_activity = Instant::now();
 
+    if game.state.needs_deal() {
+        // Players that did not send their seed in time get an empty one
+        for player in game.players.iter_mut().filter(|p| p.seed.is_none() && p.bot.is_none()) {
+            println!("Game {}: {} ran out of time to send their seed", game.id, player.id);
+            player.seed = Some(String::new());
+        }
+        advance(game);
+        return;
+    }
+    let seat = game.state.game.turn;
+    println!("Game {}: {} ran out of time", game.id, game.players[seat as usize].id);
+
+    match game.config.on_timeout {
+        TimeoutPolicy::AutoPlay => {
+            if play_first_card(game, seat) {
+                advance(game);
+            }
+        },
+        TimeoutPolicy::Forfeit => depart(game, seat as usize, Departure::TimedOut)
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// Players whose event stream stayed closed for too long leave the game
+fn detect_disconnects(game: &mut Game, recorder: &Recorder) {
+    for seat in (0..game.players.len()).rev() {
+        if game.status != GameStatus::Waiting && game.status != GameStatus::InProgress {
+            return;
+        }
+        let player = &mut game.players[seat];
+        let connected = player.sender.as_ref().is_some_and(|sender| !sender.is_closed());
+        if connected || player.bot.is_some() {
+            player.disconnected = None;
+            continue;
+        }
+        let since = *player.disconnected.get_or_insert_with(Instant::now);
+        if since.elapsed() >= DISCONNECT_GRACE {
+            game.last_activity = Instant::now();
+            depart(game, seat, Departure::Disconnected);
+            record(recorder, game, Move::Departed(seat, Departure::Disconnected));
+        }
+    }
+}
+
+async fn evict_games(games: Arc<Mutex<HashMap<usize, Game>>>, recorder: Recorder, ttl: Duration) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let mut games = games.lock().unwrap();
+        for game in games.values_mut() {
+            detect_disconnects(game, &recorder);
+        }
+        games.retain(|game_id, game| {
+            if game.last_activity.elapsed() < ttl {
+                return true;
+            }
+            match game.status {
+                GameStatus::Waiting | GameStatus::InProgress => {
+                    // Keep the game around for a while so its players can still see its state
+                    println!("Game {}: Abandoned after {:?} without activity", game_id, ttl);
+                    call_off(game, GameResult::Abandoned, GameStatus::Abandoned);
+                    game.last_activity = Instant::now();
+                    recorder.update_game(game.stored());
+                    true
+                },
+                GameStatus::Finished | GameStatus::Abandoned | GameStatus::Cancelled => {
+                    println!("Game {}: Evicted", game_id);
+                    false
                 }
-            } else {
-                // Advance to next player's turn
-                game.state.turn = (game.state.turn + 1) % game.players.len() as u8;
             }
-            warp::reply()
         });
+    }
+}
 
-    // GET /game/ -> List all awaiting games
-    // TODO: Add query string param to be able to filter: ongoing vs all
-    let list_games = warp::path("game")
-        .and(warp::get())
-        .and(games.clone())
-        .map(|games: Arc<Mutex<HashMap<usize, Game>>>| {
-            let games = games.lock().unwrap();
-            let active_games: Vec<GameInfo> = games
-                .iter()
-                .filter_map(|(id, game)| {
-                    if game.num_players > game.players.len() as u8 {
-                        Some(GameInfo {id: id.to_string(), num_players: game.num_players})
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            warp::reply::json(&active_games)
-        });
+// Requests warp itself turned down, e.g. with a malformed body, answered like any other refused request
+async fn handle_rejection(rejection: warp::Rejection) -> Result<warp::reply::Response, Infallible> {
+    if rejection.is_not_found() {
+        return Ok(error_reply(ErrorCode::NotFound));
+    }
+    Ok(error_reply(ErrorCode::InvalidRequest))
+}
 
-    let routes = create
-        .or(join)
-        .or(play)
-        .or(list_games);
+fn error_reply(error: impl Into<ErrorResponse>) -> warp::reply::Response {
+    let error: ErrorResponse = error.into();
+    let status = match error.code {
+        ErrorCode::Unauthorized | ErrorCode::InvalidCredentials => StatusCode::UNAUTHORIZED,
+        ErrorCode::GameNotFound | ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::InvalidInvite => StatusCode::FORBIDDEN,
+        ErrorCode::PlayerNotFound | ErrorCode::PlayerLeft => StatusCode::FORBIDDEN,
+        ErrorCode::UnsupportedPlayers
+        | ErrorCode::InvalidConfig
+        | ErrorCode::InvalidSeed
+        | ErrorCode::InvalidRequest
+        | ErrorCode::CardNotInHand => StatusCode::BAD_REQUEST,
+        ErrorCode::PlayerExists
+        | ErrorCode::GameFull
+        | ErrorCode::GameInProgress
+        | ErrorCode::GameNotStarted
+        | ErrorCode::GameFinished
+        | ErrorCode::NotYourTurn
+        | ErrorCode::CannotExchangeTrump
+        | ErrorCode::CannotDeclare
+        | ErrorCode::SeedNotRequested => StatusCode::CONFLICT,
+        ErrorCode::ServerError => StatusCode::INTERNAL_SERVER_ERROR
+    };
+    warp::reply::with_status(warp::reply::json(&error), status).into_response()
+}
 
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], 3030))
-        .await;
+fn deal(game_id: usize, game: &mut Game) {
+    // Seats played by bots get an empty seed. Every deck needs new seeds
+    let seeds: Vec<String> = game.players.iter_mut().map(|p| p.seed.take().unwrap_or_default()).collect();
+    game.seeds_requested = false;
+    match game.state.deal(&seeds) {
+        Ok(events) => dispatch(game, events),
+        Err(error) => eprintln!("Game {}: Could not deal: {}", game_id, error)
+    }
+}
+
+fn snapshot(game: &Game, seat: u8) -> GameSnapshot {
+    let state = &game.state.game;
+    GameSnapshot {
+        players: game.players.iter().map(|p| p.id.clone()).collect(),
+        seat,
+        teams: state.teams.clone(),
+        started: game.state.hand > 0,
+        finished: game.state.is_finished(),
+        cards: state.hands[seat as usize].clone(),
+        hand_sizes: state.hands.iter().map(|hand| hand.len() as u8).collect(),
+        deck: state.deck.len() as u8,
+        trump: state.trump.clone(),
+        played: state.played.clone(),
+        seen: state.seen.clone(),
+        score: state.team_scores(),
+        turn: state.turn,
+        round: state.round,
+        declared: state.declared.clone(),
+        exchanged: state.exchanged.clone(),
+        hand: game.state.hand,
+        wins: game.state.wins.clone(),
+        deadline: game.deadline.map(unix_millis),
+        seed_request: (game.seeds_requested && game.players[seat as usize].seed.is_none())
+            .then(|| game.state.seed_commitment())
+    }
+}
+
+fn game_result(game: &Game, outcome: Outcome) -> GameResult {
+    match outcome {
+        Outcome::Winners(seats) => GameResult::Winners(seats
+            .iter()
+            .map(|&seat| game.players[seat as usize].id.clone())
+            .collect()),
+        Outcome::Draw => GameResult::Draw
+    }
 }
 
+fn dispatch(game: &mut Game, events: Vec<rules::Event>) {
+    // Route the game rules events to the players that should receive them
+    for event in events {
+        match event {
+            rules::Event::Dealt(seat, card) => {
+                game.send(seat as usize, api::Event::NewCard(card));
+            },
+            rules::Event::Started(trump, first) => {
+                let teams = game.state.game.teams.clone();
+                let commitment = game.state.commitment.clone();
+                game.broadcast(api::Event::GameStart(trump, teams, first, commitment));
+            },
+            rules::Event::Played(seat, card) => {
+                game.broadcast(api::Event::PlayedCard(seat, card));
+            },
+            rules::Event::TrumpExchanged(seat, trump) => {
+                game.broadcast(api::Event::TrumpExchanged(seat, trump));
+            },
+            rules::Event::Declared(seat, suit, points, totals) => {
+                game.broadcast(api::Event::Declared(seat, suit, points, totals));
+            },
+            rules::Event::RoundEnd(trick, totals) => {
+                game.broadcast(api::Event::RoundEnd(trick, totals));
+            },
+            rules::Event::GameEnd(outcome, totals) => {
+                // Send the result and the final scores to all players
+                let result = game_result(game, outcome);
+                game.broadcast(api::Event::GameEnd(result, totals, game.state.reveal()));
+            },
+            rules::Event::MatchScore(wins) => {
+                game.broadcast(api::Event::MatchScore(wins));
+            },
+            rules::Event::MatchEnd(outcome, wins) => {
+                // Send who won the match to all players
+                let result = game_result(game, outcome);
+                game.result = Some(result.clone());
+                game.broadcast(api::Event::MatchEnd(result, wins));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Apply the move to the game and keep it, as the requests do
+    fn play(game: &mut Game, storage: &dyn Storage, game_move: Move) {
+        replay(game, game_move.clone());
+        storage.add_move(game.id, &game_move).unwrap();
+        storage.update_game(&game.stored()).unwrap();
+    }
 
+    #[test]
+    fn replaying_stored_moves_restores_the_game() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let config = GameConfig { match_length: 2, bots: 1, ..GameConfig::default() };
+        let state = MatchState::new(2, config.rules.clone(), config.match_length, 42).unwrap();
+        let mut game = Game::new(1, config, None, state, UNIX_EPOCH);
+        storage.add_game(&game.stored()).unwrap();
 
-fn create_sse_stream(rx: UnboundedReceiverStream<api::Event>) -> impl Stream<Item = Result<sse::Event, warp::Error>> + Send + 'static {
-    // Transforms API events to SSE event
-    rx.map(|event| Ok(sse::Event::default().data(serde_json::to_string(&event).unwrap())))
-}
\ No newline at end of file
+        // The player at seat 1 plays their first card against the bot, into the second game of the match
+        play(&mut game, &storage, Move::Joined("ann".to_string()));
+        while game.state.hand < 2 || game.state.game.round < 3 {
+            let game_move = if game.state.needs_deal() {
+                Move::Seeded(1, game.state.hand.to_string())
+            } else {
+                Move::Action(Action::Play(1, game.state.game.hands[1][0].clone()))
+            };
+            play(&mut game, &storage, game_move);
+        }
+
+        let restored = restore(&storage);
+        let restored = &restored[&1];
+        assert_eq!(restored.status, game.status);
+        assert_eq!(snapshot(restored, 1), snapshot(&game, 1));
+        let events = |game: &Game| serde_json::to_string(&game.players[1].events).unwrap();
+        assert_eq!(events(restored), events(&game));
+    }
+
+    #[test]
+    fn a_cancelled_match_cannot_be_played_on() {
+        let config = GameConfig { on_leave: LeavePolicy::Cancel, ..GameConfig::default() };
+        let state = MatchState::new(2, config.rules.clone(), config.match_length, 42).unwrap();
+        let mut game = Game::new(1, config, None, state, UNIX_EPOCH);
+        for player_id in ["ann", "bob"] {
+            replay(&mut game, Move::Joined(player_id.to_string()));
+        }
+        replay(&mut game, Move::Seeded(0, String::new()));
+        replay(&mut game, Move::Seeded(1, String::new()));
+        let seat = game.state.game.turn;
+        let other = (seat + 1) % 2;
+        let card = game.state.game.hands[seat as usize][0].clone();
+        let player_id = game.players[seat as usize].id.clone();
+        replay(&mut game, Move::Departed(other as usize, Departure::Forfeited));
+        assert_eq!(game.status, GameStatus::Cancelled);
+
+        let games = Mutex::new(HashMap::from([(1, game)]));
+        let recorder = Recorder::start(Arc::new(MemoryStorage::default()));
+        for action in [Action::Play(seat, card), Action::ExchangeTrump(seat), Action::Declare(seat, Suit::Cup)] {
+            let response = apply_action(&games, &recorder, 1, &player_id, |_| action);
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+        }
+    }
+}
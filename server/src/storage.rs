@@ -0,0 +1,431 @@
+use rusqlite::{params, Connection, OptionalExtension, Statement};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use api::{Departure, GameConfig, GameResult, GameStatus};
+use api::rules::Action;
+
+
+// Columns of the games table, in order
+type GameRow = (i64, String, Option<String>, i64, i64, String, Option<String>, String);
+
+/// Something a player or the clock did to a game. Deals and bot moves follow from them,
+/// so replaying the moves of a game on a new one with the same configuration and seed
+/// brings it back to the same state, the event log of every player included.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Move {
+    // Player id of a player taking a seat
+    Joined(String),
+    // Seat of a player reconnecting, taking their seat back from the bot.
+    // Sent a snapshot of the game if true
+    Rejoined(usize, bool),
+    // Seat of a player and the seed they sent for the next deck
+    Seeded(usize, String),
+    Action(Action),
+    // Seat of the player that left the game and how
+    Departed(usize, Departure),
+    // The player whose turn it was ran out of time
+    TimedOut
+}
+
+/// What is kept of a game besides its moves.
+#[derive(Clone, Debug)]
+pub struct StoredGame {
+    pub id: usize,
+    pub config: GameConfig,
+    pub invite: Option<String>,
+    // Seed of the match, which the config only has if it was chosen by the player who created the game
+    pub seed: u64,
+    // Seconds since the Unix epoch
+    pub created: u64,
+    pub status: GameStatus,
+    // Result of the match once it is over
+    pub result: Option<GameResult>,
+    // Games won by each team
+    pub wins: Vec<u8>
+}
+
+#[derive(Debug)]
+pub struct StorageError(String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(error: rusqlite::Error) -> StorageError {
+        StorageError(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(error: serde_json::Error) -> StorageError {
+        StorageError(error.to_string())
+    }
+}
+
+/// Where registered players, games, their moves and results are kept.
+pub trait Storage: Send + Sync {
+    /// Secret signing the session tokens, if one was kept before.
+    fn secret(&self) -> Result<Option<Vec<u8>>, StorageError>;
+
+    fn set_secret(&self, secret: &[u8]) -> Result<(), StorageError>;
+
+    /// Registered players and the hash of their passwords.
+    fn accounts(&self) -> Result<Vec<(String, String)>, StorageError>;
+
+    fn add_account(&self, player_id: &str, password_hash: &str) -> Result<(), StorageError>;
+
+    /// Highest game id given out so far. 0 if no game was ever created.
+    fn last_game_id(&self) -> Result<usize, StorageError>;
+
+    /// Games waiting for players or in progress, with their moves in order.
+    /// Games that cannot be read are left out.
+    fn open_games(&self) -> Result<Vec<(StoredGame, Vec<Move>)>, StorageError>;
+
+    fn add_game(&self, game: &StoredGame) -> Result<(), StorageError>;
+
+    /// Keep the status, result and games won of a game.
+    fn update_game(&self, game: &StoredGame) -> Result<(), StorageError>;
+
+    fn add_move(&self, game_id: usize, game_move: &Move) -> Result<(), StorageError>;
+}
+
+/// Writes games and their moves to storage in the order they are queued, on a thread of its own,
+/// so that requests never wait on the disk while holding the lock of the games.
+#[derive(Clone)]
+pub struct Recorder {
+    sender: mpsc::Sender<Write>
+}
+
+enum Write {
+    AddGame(StoredGame),
+    UpdateGame(StoredGame),
+    // A move of a game and what is kept of the game after it
+    AddMove(Move, StoredGame)
+}
+
+impl Recorder {
+    pub fn start(storage: Arc<dyn Storage>) -> Recorder {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for write in receiver {
+                let (game_id, written) = match &write {
+                    Write::AddGame(game) => (game.id, storage.add_game(game)),
+                    Write::UpdateGame(game) => (game.id, storage.update_game(game)),
+                    Write::AddMove(game_move, game) => {
+                        (game.id, storage.add_move(game.id, game_move).and_then(|_| storage.update_game(game)))
+                    }
+                };
+                if let Err(error) = written {
+                    eprintln!("Game {}: Could not store: {}", game_id, error);
+                }
+            }
+        });
+        Recorder { sender }
+    }
+
+    pub fn add_game(&self, game: StoredGame) {
+        self.write(Write::AddGame(game));
+    }
+
+    pub fn update_game(&self, game: StoredGame) {
+        self.write(Write::UpdateGame(game));
+    }
+
+    pub fn add_move(&self, game_move: Move, game: StoredGame) {
+        self.write(Write::AddMove(game_move, game));
+    }
+
+    fn write(&self, write: Write) {
+        if self.sender.send(write).is_err() {
+            eprintln!("Could not store: the storage thread has stopped");
+        }
+    }
+}
+
+/// Keeps the accounts and open games in memory, so nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStorage {
+    memory: Mutex<Memory>
+}
+
+#[derive(Default)]
+struct Memory {
+    secret: Option<Vec<u8>>,
+    accounts: Vec<(String, String)>,
+    last_game_id: usize,
+    // Open games only, dropped once they close so memory does not grow with every game played
+    games: BTreeMap<usize, (StoredGame, Vec<Move>)>
+}
+
+impl Storage for MemoryStorage {
+    fn secret(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.memory.lock().unwrap().secret.clone())
+    }
+
+    fn set_secret(&self, secret: &[u8]) -> Result<(), StorageError> {
+        self.memory.lock().unwrap().secret = Some(secret.to_vec());
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<(String, String)>, StorageError> {
+        Ok(self.memory.lock().unwrap().accounts.clone())
+    }
+
+    fn add_account(&self, player_id: &str, password_hash: &str) -> Result<(), StorageError> {
+        self.memory.lock().unwrap().accounts.push((player_id.to_string(), password_hash.to_string()));
+        Ok(())
+    }
+
+    fn last_game_id(&self) -> Result<usize, StorageError> {
+        Ok(self.memory.lock().unwrap().last_game_id)
+    }
+
+    fn open_games(&self) -> Result<Vec<(StoredGame, Vec<Move>)>, StorageError> {
+        Ok(self.memory.lock().unwrap().games
+            .values()
+            .filter(|(game, _)| is_open(&game.status))
+            .cloned()
+            .collect())
+    }
+
+    fn add_game(&self, game: &StoredGame) -> Result<(), StorageError> {
+        let mut memory = self.memory.lock().unwrap();
+        memory.last_game_id = memory.last_game_id.max(game.id);
+        memory.games.insert(game.id, (game.clone(), Vec::new()));
+        Ok(())
+    }
+
+    fn update_game(&self, game: &StoredGame) -> Result<(), StorageError> {
+        let mut memory = self.memory.lock().unwrap();
+        if !is_open(&game.status) {
+            memory.games.remove(&game.id);
+        } else if let Some((stored, _)) = memory.games.get_mut(&game.id) {
+            *stored = game.clone();
+        }
+        Ok(())
+    }
+
+    fn add_move(&self, game_id: usize, game_move: &Move) -> Result<(), StorageError> {
+        if let Some((_, moves)) = self.memory.lock().unwrap().games.get_mut(&game_id) {
+            moves.push(game_move.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Keeps everything in a SQLite database file, so games in progress survive a restart.
+/// Configurations, statuses, results and moves are stored as JSON.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>
+}
+
+impl SqliteStorage {
+    /// Open the database at path, creating it if needed.
+    pub fn open(path: &str) -> Result<SqliteStorage, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch("
+            CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS accounts (
+                player_id TEXT PRIMARY KEY,
+                password_hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS games (
+                id INTEGER PRIMARY KEY,
+                config TEXT NOT NULL,
+                invite TEXT,
+                seed INTEGER NOT NULL,
+                created INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                result TEXT,
+                wins TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS moves (
+                id INTEGER PRIMARY KEY,
+                game_id INTEGER NOT NULL REFERENCES games(id),
+                move TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS moves_game_id ON moves (game_id);
+        ")?;
+        Ok(SqliteStorage { connection: Mutex::new(connection) })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn secret(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        let connection = self.connection.lock().unwrap();
+        Ok(connection
+            .query_row("SELECT value FROM settings WHERE key = 'secret'", [], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn set_secret(&self, secret: &[u8]) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("INSERT OR REPLACE INTO settings (key, value) VALUES ('secret', ?1)", params![secret])?;
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<(String, String)>, StorageError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT player_id, password_hash FROM accounts")?;
+        let accounts = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(accounts)
+    }
+
+    fn add_account(&self, player_id: &str, password_hash: &str) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("INSERT INTO accounts (player_id, password_hash) VALUES (?1, ?2)", params![player_id, password_hash])?;
+        Ok(())
+    }
+
+    fn last_game_id(&self) -> Result<usize, StorageError> {
+        let connection = self.connection.lock().unwrap();
+        let id: i64 = connection.query_row("SELECT COALESCE(MAX(id), 0) FROM games", [], |row| row.get(0))?;
+        Ok(id as usize)
+    }
+
+    fn open_games(&self) -> Result<Vec<(StoredGame, Vec<Move>)>, StorageError> {
+        let connection = self.connection.lock().unwrap();
+        // Statuses are stored as JSON strings
+        let mut statement = connection.prepare("
+            SELECT id, config, invite, seed, created, status, result, wins FROM games
+            WHERE status IN ('\"Waiting\"', '\"InProgress\"')
+            ORDER BY id
+        ")?;
+        let rows: Vec<rusqlite::Result<GameRow>> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?)))?
+            .collect();
+
+        let mut moves = connection.prepare("SELECT move FROM moves WHERE game_id = ?1 ORDER BY id")?;
+        let mut games = Vec::new();
+        for row in rows {
+            // One game that cannot be read does not keep the others from being restored
+            let row = match row {
+                Ok(row) => row,
+                Err(error) => {
+                    eprintln!("Could not read a stored game: {}", error);
+                    continue;
+                }
+            };
+            let id = row.0;
+            match read_game(row, &mut moves) {
+                Ok(game) => games.push(game),
+                Err(error) => eprintln!("Game {}: Could not read: {}", id, error)
+            }
+        }
+        Ok(games)
+    }
+
+    fn add_game(&self, game: &StoredGame) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO games (id, config, invite, seed, created, status, result, wins) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                game.id as i64,
+                serde_json::to_string(&game.config)?,
+                game.invite,
+                game.seed as i64,
+                game.created as i64,
+                serde_json::to_string(&game.status)?,
+                game.result.as_ref().map(serde_json::to_string).transpose()?,
+                serde_json::to_string(&game.wins)?
+            ]
+        )?;
+        Ok(())
+    }
+
+    fn update_game(&self, game: &StoredGame) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "UPDATE games SET status = ?2, result = ?3, wins = ?4 WHERE id = ?1",
+            params![
+                game.id as i64,
+                serde_json::to_string(&game.status)?,
+                game.result.as_ref().map(serde_json::to_string).transpose()?,
+                serde_json::to_string(&game.wins)?
+            ]
+        )?;
+        Ok(())
+    }
+
+    fn add_move(&self, game_id: usize, game_move: &Move) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO moves (game_id, move) VALUES (?1, ?2)",
+            params![game_id as i64, serde_json::to_string(game_move)?]
+        )?;
+        Ok(())
+    }
+}
+
+// Game of a row of the games table and its moves
+fn read_game(row: GameRow, moves: &mut Statement) -> Result<(StoredGame, Vec<Move>), StorageError> {
+    let (id, config, invite, seed, created, status, result, wins) = row;
+    let game = StoredGame {
+        id: id as usize,
+        config: serde_json::from_str(&config)?,
+        invite,
+        // Seeds are kept as the signed integers SQLite stores, bit for bit
+        seed: seed as u64,
+        created: created as u64,
+        status: serde_json::from_str(&status)?,
+        result: result.map(|result| serde_json::from_str(&result)).transpose()?,
+        wins: serde_json::from_str(&wins)?
+    };
+    let game_moves = moves
+        .query_map([id], |row| row.get::<_, String>(0))?
+        .map(|game_move| Ok(serde_json::from_str(&game_move?)?))
+        .collect::<Result<_, StorageError>>()?;
+    Ok((game, game_moves))
+}
+
+// Games that are restored when the server starts again
+fn is_open(status: &GameStatus) -> bool {
+    *status == GameStatus::Waiting || *status == GameStatus::InProgress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored_game(id: usize, status: GameStatus) -> StoredGame {
+        StoredGame {
+            id,
+            config: GameConfig::default(),
+            invite: None,
+            seed: u64::MAX,
+            created: 0,
+            status,
+            result: None,
+            wins: vec![0, 0]
+        }
+    }
+
+    #[test]
+    fn only_open_games_that_can_be_read_are_restored() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        for (id, status) in [(1, GameStatus::Waiting), (2, GameStatus::InProgress), (3, GameStatus::Finished), (4, GameStatus::InProgress)] {
+            storage.add_game(&stored_game(id, status)).unwrap();
+            storage.add_move(id, &Move::Joined("ann".to_string())).unwrap();
+        }
+        storage.connection.lock().unwrap().execute("UPDATE games SET config = 'not json' WHERE id = 2", []).unwrap();
+
+        let games = storage.open_games().unwrap();
+        let ids: Vec<usize> = games.iter().map(|(game, _)| game.id).collect();
+        assert_eq!(ids, vec![1, 4]);
+        assert_eq!(games[0].0.seed, u64::MAX);
+        assert_eq!(games[1].1.len(), 1);
+    }
+}
@@ -0,0 +1,254 @@
+use api::bot::BotLevel;
+use api::fair;
+use api::rules::{GameState, Outcome, Phase, Rules, Variant};
+use api::strategy::{self, PlayerView, Strategy};
+use serde::Serialize;
+use std::env;
+
+
+type NewStrategy = fn() -> Box<dyn Strategy>;
+
+// Strategies that can enter a tournament, by name
+static STRATEGIES: &[(&str, NewStrategy)] = &[
+    ("simple", || Box::new(BotLevel::Simple)),
+    ("strong", || Box::new(BotLevel::Strong))
+];
+
+// z-score of the 95% confidence intervals
+static Z: f64 = 1.96;
+
+static USAGE: &str = "Run command: tournament [--deals N] [--seed N] [--format json|csv] [--variant classic|two-cards] \
+    [--teams] [--trump-exchange] [--declarations] [strategy] [strategy] ...";
+
+#[derive(PartialEq)]
+enum Format {
+    Json,
+    Csv
+}
+
+struct Options {
+    // Strategy sitting at each seat. Seats are rotated so every strategy plays every deal from every seat
+    lineup: Vec<String>,
+    deals: u64,
+    seed: u64,
+    rules: Rules,
+    format: Format
+}
+
+/// Results of every game played by one strategy. A strategy sitting at several seats counts once per game.
+#[derive(Default)]
+struct Tally {
+    games: u32,
+    wins: u32,
+    draws: u32,
+    points: f64,
+    points_squared: f64
+}
+
+#[derive(Serialize)]
+struct Standing {
+    strategy: String,
+    games: u32,
+    wins: u32,
+    // Shared the highest score with another team
+    draws: u32,
+    losses: u32,
+    win_rate: f64,
+    win_rate_low: f64,
+    win_rate_high: f64,
+    // Points of the strategy's team at the end of each game
+    average_points: f64,
+    points_low: f64,
+    points_high: f64
+}
+
+#[derive(Serialize)]
+struct Report {
+    lineup: Vec<String>,
+    rules: Rules,
+    deals: u64,
+    seed: u64,
+    games: u64,
+    standings: Vec<Standing>
+}
+
+fn main() {
+    let options = parse_args(env::args().skip(1).collect());
+    let num_players = options.lineup.len() as u8;
+    if GameState::new(num_players, options.rules.clone()).is_err() {
+        panic!("The rules do not support a game of {} players", num_players);
+    }
+
+    let mut entrants: Vec<Box<dyn Strategy>> = options.lineup
+        .iter()
+        .map(|name| strategy(name).unwrap_or_else(|| panic!("Unknown strategy {}. {}", name, USAGE)))
+        .collect();
+    let mut tallies: Vec<(String, Tally)> = Vec::new();
+    for name in &options.lineup {
+        if !tallies.iter().any(|(other, _)| other == name) {
+            tallies.push((name.clone(), Tally::default()));
+        }
+    }
+
+    for deal in 0..options.deals {
+        // Every deal has its own seed so that any game can be replayed on its own, with the
+        // same shuffle as the server so the deck does not change with the rand version
+        let deck = fair::shuffle(num_players, &format!("{}-{}", options.seed, deal), &[]);
+
+        for rotation in 0..num_players {
+            // Entrant sitting at each seat
+            let seats: Vec<usize> = (0..num_players)
+                .map(|seat| ((seat + num_players - rotation) % num_players) as usize)
+                .collect();
+            let state = play_game(&options, deck.clone(), &mut entrants, &seats);
+
+            let scores = state.team_scores();
+            let mut tallied: Vec<&str> = Vec::new();
+            for (seat, &entrant) in seats.iter().enumerate() {
+                // Games of a strategy against itself would otherwise count twice and narrow the intervals
+                if tallied.contains(&options.lineup[entrant].as_str()) {
+                    continue;
+                }
+                tallied.push(&options.lineup[entrant]);
+                let (_, tally) = tallies
+                    .iter_mut()
+                    .find(|(name, _)| *name == options.lineup[entrant])
+                    .unwrap();
+                let points = scores[state.teams[seat] as usize] as f64;
+                tally.games += 1;
+                tally.points += points;
+                tally.points_squared += points * points;
+                match result(&state, seat as u8) {
+                    Some(true) => tally.wins += 1,
+                    Some(false) => tally.draws += 1,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    let report = Report {
+        lineup: options.lineup.clone(),
+        rules: options.rules.clone(),
+        deals: options.deals,
+        seed: options.seed,
+        games: options.deals * num_players as u64,
+        standings: tallies.into_iter().map(|(name, tally)| standing(name, &tally)).collect()
+    };
+    match options.format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        Format::Csv => print_csv(&report)
+    }
+}
+
+fn strategy(name: &str) -> Option<Box<dyn Strategy>> {
+    STRATEGIES
+        .iter()
+        .find(|(registered, _)| *registered == name)
+        .map(|(_, new)| new())
+}
+
+fn parse_args(args: Vec<String>) -> Options {
+    let mut options = Options {
+        lineup: Vec::new(),
+        deals: 1000,
+        seed: 0,
+        rules: Rules::default(),
+        format: Format::Json
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--deals" => options.deals = args.next().and_then(|n| n.parse().ok()).expect(USAGE),
+            "--seed" => options.seed = args.next().and_then(|n| n.parse().ok()).expect(USAGE),
+            "--format" => options.format = match args.next().as_deref() {
+                Some("json") => Format::Json,
+                Some("csv") => Format::Csv,
+                _ => panic!("{}", USAGE)
+            },
+            "--variant" => options.rules.variant = match args.next().as_deref() {
+                Some("classic") => Variant::Classic,
+                Some("two-cards") => Variant::TwoCards,
+                _ => panic!("{}", USAGE)
+            },
+            "--teams" => options.rules.teams = true,
+            "--trump-exchange" => options.rules.trump_exchange = true,
+            "--declarations" => options.rules.declarations = true,
+            _ if arg.starts_with("--") => panic!("{}", USAGE),
+            _ => options.lineup.push(arg)
+        }
+    }
+    if options.lineup.len() < 2 || options.deals == 0 {
+        panic!("{}", USAGE);
+    }
+    options
+}
+
+// Play a whole game, asking the entrant sitting at each seat for their moves
+fn play_game(options: &Options, deck: Vec<api::Card>, entrants: &mut [Box<dyn Strategy>], seats: &[usize]) -> GameState {
+    let mut state = GameState::new(seats.len() as u8, options.rules.clone()).unwrap();
+    state.start(deck, 0).unwrap();
+
+    while state.phase == Phase::Playing {
+        let seat = state.turn;
+        let entrant = seats[seat as usize];
+        let action = strategy::next_action(entrants[entrant].as_mut(), &PlayerView::new(&state, seat));
+        if let Err(error) = state.apply(action.clone()) {
+            panic!("Strategy {} could not make the move {:?}: {}", options.lineup[entrant], action, error);
+        }
+    }
+    state
+}
+
+// Whether the player sitting at seat won (true) or drew (false) the game. None if they lost
+fn result(state: &GameState, seat: u8) -> Option<bool> {
+    match state.outcome() {
+        Outcome::Draw => Some(false),
+        Outcome::Winners(seats) if seats.contains(&seat) => {
+            // Sharing the highest score with another team is a draw
+            let team = state.teams[seat as usize];
+            Some(seats.iter().all(|&other| state.teams[other as usize] == team))
+        },
+        Outcome::Winners(_) => None
+    }
+}
+
+fn standing(strategy: String, tally: &Tally) -> Standing {
+    let games = tally.games as f64;
+    let win_rate = tally.wins as f64 / games;
+    // Wilson score interval of the win rate
+    let z2 = Z * Z;
+    let centre = (win_rate + z2 / (2.0 * games)) / (1.0 + z2 / games);
+    let margin = Z * (win_rate * (1.0 - win_rate) / games + z2 / (4.0 * games * games)).sqrt() / (1.0 + z2 / games);
+
+    let average_points = tally.points / games;
+    let variance = (tally.points_squared / games - average_points * average_points).max(0.0);
+    let points_margin = Z * (variance / games).sqrt();
+
+    Standing {
+        strategy,
+        games: tally.games,
+        wins: tally.wins,
+        draws: tally.draws,
+        losses: tally.games - tally.wins - tally.draws,
+        win_rate,
+        win_rate_low: centre - margin,
+        win_rate_high: centre + margin,
+        average_points,
+        points_low: average_points - points_margin,
+        points_high: average_points + points_margin
+    }
+}
+
+fn print_csv(report: &Report) {
+    println!("strategy,games,wins,draws,losses,win_rate,win_rate_low,win_rate_high,average_points,points_low,points_high");
+    for s in &report.standings {
+        println!(
+            "{},{},{},{},{},{:.4},{:.4},{:.4},{:.2},{:.2},{:.2}",
+            s.strategy, s.games, s.wins, s.draws, s.losses,
+            s.win_rate, s.win_rate_low, s.win_rate_high,
+            s.average_points, s.points_low, s.points_high
+        );
+    }
+}